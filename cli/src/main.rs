@@ -1,11 +1,32 @@
 #![allow(dead_code, unused_variables)]
 
-use clap::Parser;
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+use clap::{Parser, ValueEnum};
+use extricrate::{
+    dependencies::{ModulePath, get_crate_name, list_modules},
+    graph::ModuleGraph,
+    manifest::VersionPinning,
+    metrics::{self, MetricsSortKey},
+    partition::{self, PartitionTarget, plan_partition},
+    plan::{ExtractionOptions, ExtractionPlan, plan_extraction, undo_last_extraction},
+    suggest::{self, suggest_extractions},
+    verify::verify_extraction,
+};
 
 /// Extricrate is a refactoring tool to extract a crate.
 #[derive(Debug, Parser, Clone)]
 #[clap(version, author)]
 pub struct CLIOpts {
+    /// Root of the crate to analyze, containing its Cargo.toml.
+    #[clap(
+        long,
+        global = true,
+        env = "EXTRICRATE_CRATE_ROOT",
+        default_value = "."
+    )]
+    pub crate_root: PathBuf,
     /// Export
     #[clap(subcommand)]
     pub command: Command,
@@ -19,6 +40,24 @@ pub enum Command {
     /// Extract a module to a separate crate.
     #[clap(name = "extract")]
     Extract(ExtractOpts),
+    /// Plan the extraction of a module to a separate crate, to review it before applying it.
+    #[clap(name = "plan")]
+    Plan(PlanOpts),
+    /// Apply an extraction plan, unless the crate changed since it was planned.
+    #[clap(name = "apply")]
+    Apply(ApplyOpts),
+    /// Revert the last extraction, unless the files it wrote changed since.
+    #[clap(name = "undo")]
+    Undo,
+    /// Report coupling and size metrics for every module.
+    #[clap(name = "metrics")]
+    Metrics(MetricsOpts),
+    /// Rank the modules by how cheaply they could be extracted to a separate crate.
+    #[clap(name = "suggest")]
+    Suggest(SuggestOpts),
+    /// Plan how to split the crate into several acyclic crates.
+    #[clap(name = "partition")]
+    Partition(PartitionOpts),
 }
 
 #[derive(Debug, Parser, Clone)]
@@ -29,22 +68,328 @@ pub struct ListDependenciesOpts {
 }
 
 #[derive(Debug, Parser, Clone)]
-pub struct ExtractOpts {
-    /// Module to extract to a crate.
-    #[clap(long, env = "EXTRICRATE_MODULE")]
-    pub module: String,
+pub struct ExtractionOpts {
+    /// Module to extract to a crate. Repeat it to extract several modules into the same crate.
+    #[clap(
+        long,
+        env = "EXTRICRATE_MODULE",
+        value_delimiter = ',',
+        required = true
+    )]
+    pub module: Vec<String>,
     /// Target crate to create.
     #[clap(long, env = "EXTRICRATE_CRATE_NAME")]
     pub crate_name: String,
+    /// Directory of the crate to create. Defaults to a sibling of the current crate.
+    #[clap(long)]
+    pub crate_path: Option<PathBuf>,
+    /// Also extract the modules of the crate that the module depends on.
+    #[clap(long)]
+    pub with_dependencies: bool,
+    /// Move the dependencies that both crates use to `[workspace.dependencies]`.
+    #[clap(long)]
+    pub hoist_dependencies: bool,
+    /// Rewrite the version requirements of the new crate from the versions in Cargo.lock.
+    #[clap(long, value_enum)]
+    pub pin_versions: Option<PinVersions>,
+}
+
+impl From<&ExtractionOpts> for ExtractionOptions {
+    fn from(value: &ExtractionOpts) -> Self {
+        ExtractionOptions {
+            with_dependencies: value.with_dependencies,
+            hoist_dependencies: value.hoist_dependencies,
+            pinning: value.pin_versions.map(VersionPinning::from),
+        }
+    }
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct ExtractOpts {
+    #[clap(flatten)]
+    pub extraction: ExtractionOpts,
+    /// Print the files the extraction would create, move and edit, without writing anything.
+    #[clap(long, conflicts_with = "verify")]
+    pub dry_run: bool,
+    #[clap(flatten)]
+    pub verify: VerifyOpts,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct VerifyOpts {
+    /// Check that the crates still compile after the extraction, with `cargo check`.
+    #[clap(long)]
+    pub verify: bool,
+    /// Also build the tests when verifying, with `cargo test --no-run`.
+    #[clap(long, requires = "verify")]
+    pub verify_tests: bool,
+    /// Undo the extraction if the verification fails.
+    #[clap(long, requires = "verify")]
+    pub rollback_on_failure: bool,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct PlanOpts {
+    #[clap(flatten)]
+    pub extraction: ExtractionOpts,
+    /// File to write the plan to, as JSON. Defaults to the standard output.
+    #[clap(long)]
+    pub out: Option<PathBuf>,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct ApplyOpts {
+    /// Plan written by the `plan` command.
+    pub plan: PathBuf,
+    #[clap(flatten)]
+    pub verify: VerifyOpts,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct MetricsOpts {
+    /// Column to sort the report by.
+    #[clap(long, value_enum, default_value_t = SortKey::Module)]
+    pub sort_by: SortKey,
+    /// Output format.
+    #[clap(long, value_enum, default_value_t = OutputFormat::Table)]
+    pub format: OutputFormat,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct SuggestOpts {
+    /// Maximum number of suggestions to show.
+    #[clap(long, default_value_t = 20)]
+    pub limit: usize,
+    /// Output format.
+    #[clap(long, value_enum, default_value_t = ReportFormat::Text)]
+    pub format: ReportFormat,
+}
+
+#[derive(Debug, Parser, Clone)]
+pub struct PartitionOpts {
+    /// Maximum number of crates to create.
+    #[clap(
+        long,
+        required_unless_present = "max_lines",
+        conflicts_with = "max_lines"
+    )]
+    pub crates: Option<usize>,
+    /// Maximum number of lines of each created crate.
+    #[clap(long)]
+    pub max_lines: Option<usize>,
+    /// Depth of the smallest subtrees to move around: 1 for the children of the crate root.
+    #[clap(long, default_value_t = 1)]
+    pub depth: usize,
+    /// Output format.
+    #[clap(long, value_enum, default_value_t = ReportFormat::Text)]
+    pub format: ReportFormat,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum ReportFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum SortKey {
+    Module,
+    FanIn,
+    FanOut,
+    Instability,
+    InternalDependencies,
+    ExternalDependencies,
+    Lines,
+    Items,
+}
+
+impl From<SortKey> for MetricsSortKey {
+    fn from(value: SortKey) -> Self {
+        match value {
+            SortKey::Module => MetricsSortKey::Module,
+            SortKey::FanIn => MetricsSortKey::FanIn,
+            SortKey::FanOut => MetricsSortKey::FanOut,
+            SortKey::Instability => MetricsSortKey::Instability,
+            SortKey::InternalDependencies => MetricsSortKey::InternalDependencies,
+            SortKey::ExternalDependencies => MetricsSortKey::ExternalDependencies,
+            SortKey::Lines => MetricsSortKey::Lines,
+            SortKey::Items => MetricsSortKey::Items,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum PinVersions {
+    /// `=1.2.3`, only the locked version.
+    Exact,
+    /// `1.2.3`, the locked version or a newer compatible one.
+    Minimal,
+}
+
+impl From<PinVersions> for VersionPinning {
+    fn from(value: PinVersions) -> Self {
+        match value {
+            PinVersions::Exact => VersionPinning::Exact,
+            PinVersions::Minimal => VersionPinning::Minimal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum OutputFormat {
+    Table,
+    Csv,
+    Json,
 }
 
 mod logging;
 
+fn print_metrics(crate_root: &Path, opts: &MetricsOpts) -> Result<(), Box<dyn Error>> {
+    let graph = ModuleGraph::new(&list_modules(crate_root)?);
+    let mut report = metrics::compute_metrics(&graph);
+    metrics::sort_metrics(&mut report, opts.sort_by.into());
+    match opts.format {
+        OutputFormat::Table => print!("{}", metrics::to_table(&report)),
+        OutputFormat::Csv => print!("{}", metrics::to_csv(&report)),
+        OutputFormat::Json => println!("{}", metrics::to_json(&report)?),
+    }
+    Ok(())
+}
+
+fn print_suggestions(crate_root: &Path, opts: &SuggestOpts) -> Result<(), Box<dyn Error>> {
+    let crate_modules = list_modules(crate_root)?;
+    let mut suggestions = suggest_extractions(
+        &ModuleGraph::new(&crate_modules),
+        &crate_modules.use_statements,
+    );
+    suggestions.truncate(opts.limit);
+    match opts.format {
+        ReportFormat::Text => print!("{}", suggest::to_text(&suggestions)),
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&suggestions)?),
+    }
+    Ok(())
+}
+
+fn print_partition(crate_root: &Path, opts: &PartitionOpts) -> Result<(), Box<dyn Error>> {
+    let graph = ModuleGraph::new(&list_modules(crate_root)?);
+    let target = match (opts.crates, opts.max_lines) {
+        (Some(crates), _) => PartitionTarget::Crates(crates),
+        (None, Some(max_lines)) => PartitionTarget::MaxLines(max_lines),
+        (None, None) => unreachable!("clap requires one of --crates and --max-lines"),
+    };
+    let plan = plan_partition(&graph, &get_crate_name(crate_root)?, target, opts.depth);
+    match opts.format {
+        ReportFormat::Text => print!("{}", partition::to_text(&plan)),
+        ReportFormat::Json => println!("{}", serde_json::to_string_pretty(&plan)?),
+    }
+    Ok(())
+}
+
+fn plan(crate_root: &Path, opts: &ExtractionOpts) -> Result<ExtractionPlan, Box<dyn Error>> {
+    let crate_modules = list_modules(crate_root)?;
+    let target_crate_root = match &opts.crate_path {
+        Some(crate_path) => crate_path.clone(),
+        None => crate_root.join("..").join(&opts.crate_name),
+    };
+    plan_extraction(
+        crate_root,
+        &opts
+            .module
+            .iter()
+            .map(|module| ModulePath::from(module.as_str()))
+            .collect::<Vec<_>>(),
+        &opts.crate_name,
+        &target_crate_root,
+        &crate_modules,
+        &ExtractionOptions::from(opts),
+    )
+}
+
+fn extract(crate_root: &Path, opts: &ExtractOpts) -> Result<(), Box<dyn Error>> {
+    let plan = plan(crate_root, &opts.extraction)?;
+    if opts.dry_run {
+        print!("{}", plan.to_diff()?);
+        return Ok(());
+    }
+    apply(&plan, &opts.verify)
+}
+
+fn apply(plan: &ExtractionPlan, opts: &VerifyOpts) -> Result<(), Box<dyn Error>> {
+    let edits = if opts.verify {
+        plan.applied_edits()?
+    } else {
+        Vec::new()
+    };
+    let journal_path = plan.apply()?;
+    println!(
+        "Extracted {}, the journal to undo it is at {}",
+        plan.target_crate_name,
+        journal_path.display()
+    );
+    if !opts.verify {
+        return Ok(());
+    }
+    // Failing to run cargo at all, e.g. on a broken manifest, is a failed verification too.
+    let failure = match verify_extraction(
+        &[&plan.crate_root, &plan.target_crate_root],
+        &edits,
+        opts.verify_tests,
+    ) {
+        Ok(errors) if errors.is_empty() => return Ok(()),
+        Ok(errors) => {
+            for error in &errors {
+                eprintln!("{error}");
+            }
+            format!("the extraction doesn't compile ({} errors)", errors.len())
+        }
+        Err(error) => format!("the extraction couldn't be verified: {error}"),
+    };
+    if opts.rollback_on_failure {
+        undo_last_extraction(&plan.crate_root)?;
+        return Err(format!("{failure}, it was undone").into());
+    }
+    Err(format!("{failure}, run `cargo extricrate undo` to revert it").into())
+}
+
+fn write_plan(crate_root: &Path, opts: &PlanOpts) -> Result<(), Box<dyn Error>> {
+    let plan = serde_json::to_string_pretty(&plan(crate_root, &opts.extraction)?)?;
+    match &opts.out {
+        Some(out) => std::fs::write(out, plan + "\n")?,
+        None => println!("{plan}"),
+    }
+    Ok(())
+}
+
+fn apply_plan(opts: &ApplyOpts) -> Result<(), Box<dyn Error>> {
+    let plan: ExtractionPlan = serde_json::from_str(&std::fs::read_to_string(&opts.plan)?)?;
+    apply(&plan, &opts.verify)
+}
+
+fn run(opts: CLIOpts) -> Result<(), Box<dyn Error>> {
+    match opts.command {
+        Command::ListDependencies(opts) => todo!(),
+        Command::Extract(extract_opts) => extract(&opts.crate_root, &extract_opts),
+        Command::Plan(plan_opts) => write_plan(&opts.crate_root, &plan_opts),
+        Command::Apply(apply_opts) => apply_plan(&apply_opts),
+        Command::Undo => {
+            let journal_path = undo_last_extraction(&opts.crate_root)?;
+            println!(
+                "Undid the extraction recorded in {}",
+                journal_path.display()
+            );
+            Ok(())
+        }
+        Command::Metrics(metrics_opts) => print_metrics(&opts.crate_root, &metrics_opts),
+        Command::Suggest(suggest_opts) => print_suggestions(&opts.crate_root, &suggest_opts),
+        Command::Partition(partition_opts) => print_partition(&opts.crate_root, &partition_opts),
+    }
+}
+
 fn main() {
     let opts = CLIOpts::parse();
     logging::init();
-    match opts.command {
-        Command::ListDependencies(opts) => todo!(),
-        Command::Extract(opts) => todo!(),
+    if let Err(error) = run(opts) {
+        eprintln!("error: {error}");
+        std::process::exit(1);
     }
 }
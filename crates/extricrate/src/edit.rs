@@ -0,0 +1,251 @@
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use proc_macro2::Span;
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum EditError {
+    #[error("overlapping edits in {file}: {first:?} and {second:?}")]
+    OverlappingEdits {
+        file: PathBuf,
+        first: Range<usize>,
+        second: Range<usize>,
+    },
+    #[error("edit {range:?} is out of bounds or not on a character boundary in {file}")]
+    InvalidRange { file: PathBuf, range: Range<usize> },
+}
+
+/// Replacement of a byte range of a file by some text. Insertions have an empty range, deletions
+/// an empty text.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextEdit {
+    pub range: Range<usize>,
+    pub text: String,
+}
+
+impl TextEdit {
+    pub fn replace(range: Range<usize>, text: impl Into<String>) -> Self {
+        Self {
+            range,
+            text: text.into(),
+        }
+    }
+
+    /// Replaces the source text of `span`, which must come from the file being edited.
+    pub fn replace_span(span: Span, text: impl Into<String>) -> Self {
+        Self::replace(span.byte_range(), text)
+    }
+
+    pub fn insert(offset: usize, text: impl Into<String>) -> Self {
+        Self::replace(offset..offset, text)
+    }
+
+    pub fn delete(range: Range<usize>) -> Self {
+        Self::replace(range, "")
+    }
+}
+
+/// Edits to apply to a single file.
+///
+/// The edits are computed from the spans of the parsed file, and applied to its original text
+/// so that comments and formatting outside of them are preserved.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SourceChange {
+    /// Path of the file, relative to the crate root.
+    pub file: PathBuf,
+    pub edits: Vec<TextEdit>,
+}
+
+impl SourceChange {
+    pub fn new(file: impl Into<PathBuf>) -> Self {
+        Self {
+            file: file.into(),
+            edits: Vec::new(),
+        }
+    }
+
+    pub fn push(&mut self, edit: TextEdit) {
+        self.edits.push(edit);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.edits.is_empty()
+    }
+
+    /// Applies the edits to the content of the file.
+    pub fn apply(&self, content: &str) -> Result<String, EditError> {
+        apply_edits(&self.file, content, 0, &self.edits)
+    }
+
+    /// Applies the edits to the content of the file, and formats the result as a unified diff.
+    pub fn diff(&self, content: &str) -> Result<String, EditError> {
+        Ok(unified_diff(&self.file, content, &self.apply(content)?))
+    }
+}
+
+/// Applies `edits` to `text`, which starts at byte `offset` of `file`.
+///
+/// Edits may be given in any order. Insertions at the same offset are applied in the given
+/// order, but no other edits may overlap.
+pub fn apply_edits(
+    file: &Path,
+    text: &str,
+    offset: usize,
+    edits: &[TextEdit],
+) -> Result<String, EditError> {
+    let mut edits = edits.iter().collect::<Vec<_>>();
+    edits.sort_by_key(|edit| (edit.range.start, edit.range.end));
+    let mut result = String::with_capacity(text.len());
+    let mut position = 0;
+    let mut previous: Option<&TextEdit> = None;
+    for edit in edits {
+        let invalid_range = || EditError::InvalidRange {
+            file: file.to_owned(),
+            range: edit.range.clone(),
+        };
+        let start = edit
+            .range
+            .start
+            .checked_sub(offset)
+            .ok_or_else(invalid_range)?;
+        let end = edit
+            .range
+            .end
+            .checked_sub(offset)
+            .ok_or_else(invalid_range)?;
+        if start > end
+            || end > text.len()
+            || !text.is_char_boundary(start)
+            || !text.is_char_boundary(end)
+        {
+            return Err(invalid_range());
+        }
+        if let Some(previous) = previous
+            && edit.range.start < previous.range.end
+        {
+            return Err(EditError::OverlappingEdits {
+                file: file.to_owned(),
+                first: previous.range.clone(),
+                second: edit.range.clone(),
+            });
+        }
+        result.push_str(&text[position..start]);
+        result.push_str(&edit.text);
+        position = end;
+        previous = Some(edit);
+    }
+    result.push_str(&text[position..]);
+    Ok(result)
+}
+
+/// Formats the changes from `old` to `new` as a unified diff of `file`, empty if they are equal.
+/// An empty side stands for a created or deleted file.
+pub fn unified_diff(file: &Path, old: &str, new: &str) -> String {
+    if old == new {
+        return String::new();
+    }
+    let old_header = if old.is_empty() {
+        "/dev/null".to_owned()
+    } else {
+        format!("a/{}", file.display())
+    };
+    let new_header = if new.is_empty() {
+        "/dev/null".to_owned()
+    } else {
+        format!("b/{}", file.display())
+    };
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(&old_header, &new_header)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use pretty_assertions::assert_eq;
+
+    use super::{EditError, SourceChange, TextEdit, unified_diff};
+
+    const SOURCE: &str = "// Imports.\nuse crate::auth::Token;\n\nfn main() {}\n";
+
+    #[test]
+    fn applies_edits_in_any_order() {
+        let mut change = SourceChange::new("src/main.rs");
+        change.push(TextEdit::insert(SOURCE.len(), "\nfn other() {}\n"));
+        change.push(TextEdit::replace(16..27, "my_crate_auth"));
+        change.push(TextEdit::delete(0..12));
+        assert_eq!(
+            change.apply(SOURCE),
+            Ok("use my_crate_auth::Token;\n\nfn main() {}\n\nfn other() {}\n".to_owned())
+        );
+    }
+
+    #[test]
+    fn replaces_spans() {
+        let file = syn::parse_file(SOURCE).unwrap();
+        let syn::Item::Use(item) = &file.items[0] else {
+            panic!("expected a use statement");
+        };
+        let mut change = SourceChange::new("src/main.rs");
+        change.push(TextEdit::replace_span(
+            syn::spanned::Spanned::span(&item.tree),
+            "std::fmt",
+        ));
+        assert_eq!(
+            change.apply(SOURCE),
+            Ok("// Imports.\nuse std::fmt;\n\nfn main() {}\n".to_owned())
+        );
+    }
+
+    #[test]
+    fn detects_overlapping_edits() {
+        let mut change = SourceChange::new("src/main.rs");
+        change.push(TextEdit::replace(16..27, "my_crate_auth"));
+        change.push(TextEdit::delete(12..36));
+        assert_eq!(
+            change.apply(SOURCE),
+            Err(EditError::OverlappingEdits {
+                file: PathBuf::from("src/main.rs"),
+                first: 12..36,
+                second: 16..27,
+            })
+        );
+        let mut change = SourceChange::new("src/main.rs");
+        change.push(TextEdit::delete(0..1000));
+        assert_eq!(
+            change.apply(SOURCE),
+            Err(EditError::InvalidRange {
+                file: PathBuf::from("src/main.rs"),
+                range: 0..1000,
+            })
+        );
+    }
+
+    #[test]
+    fn formats_unified_diff() {
+        let mut change = SourceChange::new("src/main.rs");
+        change.push(TextEdit::replace(16..27, "my_crate_auth"));
+        assert_eq!(
+            change.diff(SOURCE).unwrap(),
+            concat!(
+                "--- a/src/main.rs\n",
+                "+++ b/src/main.rs\n",
+                "@@ -1,4 +1,4 @@\n",
+                " // Imports.\n",
+                "-use crate::auth::Token;\n",
+                "+use my_crate_auth::Token;\n",
+                " \n",
+                " fn main() {}\n",
+            )
+        );
+        assert_eq!(
+            unified_diff(Path::new("src/lib.rs"), "", "pub mod auth;\n"),
+            "--- /dev/null\n+++ b/src/lib.rs\n@@ -0,0 +1 @@\n+pub mod auth;\n"
+        );
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::dependencies::{
+    CrateModules, GetAllModuleFilesError, ModuleDependencies, ModuleInfo, ModuleList, ModuleName,
+    ModuleTree, list_dependencies,
+};
+
+/// Dependency graph between the modules of a crate.
+#[derive(Debug)]
+pub struct ModuleGraph {
+    modules: ModuleTree,
+    /// Dependencies as written in the `use` statements, including other crates, whose paths
+    /// start with the package they come from rather than the name used in the code.
+    raw_dependencies: ModuleDependencies,
+    /// Crate-local dependencies, resolved to modules of the tree.
+    dependencies: BTreeMap<ModuleName, BTreeSet<ModuleName>>,
+    /// Reverse of `dependencies`.
+    dependents: BTreeMap<ModuleName, BTreeSet<ModuleName>>,
+}
+
+impl ModuleGraph {
+    pub fn new(crate_modules: &CrateModules) -> Self {
+        let modules = crate_modules.modules.clone();
+        let raw_dependencies = list_dependencies(&crate_modules.use_statements)
+            .into_iter()
+            .map(|(source, targets)| {
+                let targets = targets
+                    .into_iter()
+                    .map(|target| attribute_to_package(&crate_modules.packages, target))
+                    .collect();
+                (source, targets)
+            })
+            .collect::<ModuleDependencies>();
+        let mut dependencies: BTreeMap<ModuleName, BTreeSet<ModuleName>> = BTreeMap::new();
+        let mut dependents: BTreeMap<ModuleName, BTreeSet<ModuleName>> = BTreeMap::new();
+        for (source, targets) in &raw_dependencies {
+            for target in targets {
+                let Some(target) = resolve_module(&modules, target) else {
+                    continue;
+                };
+                if &target == source {
+                    continue;
+                }
+                dependents
+                    .entry(target.clone())
+                    .or_default()
+                    .insert(source.clone());
+                dependencies
+                    .entry(source.clone())
+                    .or_default()
+                    .insert(target);
+            }
+        }
+        Self {
+            modules,
+            raw_dependencies,
+            dependencies,
+            dependents,
+        }
+    }
+
+    pub fn modules(&self) -> &ModuleTree {
+        &self.modules
+    }
+
+    pub fn module(&self, module: &ModuleName) -> Option<&ModuleInfo> {
+        self.modules.get(module)
+    }
+
+    /// Dependencies of `module` as written in its `use` statements, including other crates.
+    pub fn raw_dependencies<'a>(
+        &'a self,
+        module: &ModuleName,
+    ) -> impl Iterator<Item = &'a ModuleName> + use<'a> {
+        self.raw_dependencies.get(module).into_iter().flatten()
+    }
+
+    /// Modules of the crate that `module` depends on.
+    pub fn dependencies<'a>(
+        &'a self,
+        module: &ModuleName,
+    ) -> impl Iterator<Item = &'a ModuleName> + use<'a> {
+        self.dependencies.get(module).into_iter().flatten()
+    }
+
+    /// Modules of the crate that depend on `module`.
+    pub fn dependents<'a>(
+        &'a self,
+        module: &ModuleName,
+    ) -> impl Iterator<Item = &'a ModuleName> + use<'a> {
+        self.dependents.get(module).into_iter().flatten()
+    }
+
+    /// `root` and all its submodules.
+    pub fn subtree<'a>(&'a self, root: &'a ModuleName) -> impl Iterator<Item = &'a ModuleName> {
+        self.modules
+            .keys()
+            .filter(move |module| module.is_descendant_of(root))
+    }
+
+    /// Computes the smallest set of subtrees containing `modules` and everything they
+    /// transitively depend on in the crate, returned as their roots.
+    ///
+    /// Fails if that set would contain the crate root, i.e. if there is no way to extract the
+    /// modules without taking the whole crate along.
+    pub fn extraction_closure(
+        &self,
+        modules: &[ModuleName],
+    ) -> Result<Vec<ModuleName>, GetAllModuleFilesError> {
+        let mut roots = BTreeSet::new();
+        for module in modules {
+            insert_subtree_root(&mut roots, module.clone());
+        }
+        loop {
+            let covered = self
+                .modules
+                .keys()
+                .filter(|candidate| roots.iter().any(|root| candidate.is_descendant_of(root)))
+                .collect::<BTreeSet<_>>();
+            let needed = covered
+                .iter()
+                .flat_map(|covered_module| self.dependencies(covered_module))
+                .filter(|dependency| !covered.contains(dependency))
+                .cloned()
+                .collect::<BTreeSet<_>>();
+            if needed.is_empty() {
+                return Ok(roots.into_iter().collect());
+            }
+            if let Some(crate_root) = needed
+                .iter()
+                .find(|dependency| dependency.parent().is_none())
+            {
+                let importers = covered
+                    .iter()
+                    .filter(|covered_module| {
+                        self.dependencies(covered_module)
+                            .any(|dependency| dependency == crate_root)
+                    })
+                    .map(|covered_module| (*covered_module).clone())
+                    .collect();
+                return Err(GetAllModuleFilesError::NoClosedModuleSet(ModuleList(
+                    importers,
+                )));
+            }
+            for dependency in needed {
+                insert_subtree_root(&mut roots, dependency);
+            }
+        }
+    }
+
+    /// Resolves a path from a `use` statement to the module of the crate it points into.
+    pub fn resolve(&self, path: &ModuleName) -> Option<ModuleName> {
+        resolve_module(&self.modules, path)
+    }
+}
+
+/// Adds `module` to a set of subtree roots, unless an ancestor is already there, removing its
+/// descendants.
+fn insert_subtree_root(roots: &mut BTreeSet<ModuleName>, module: ModuleName) {
+    if roots.iter().any(|root| module.is_descendant_of(root)) {
+        return;
+    }
+    roots.retain(|root| !root.is_descendant_of(&module));
+    roots.insert(module);
+}
+
+/// Replaces the first segment of a path into another crate with the package it comes from,
+/// e.g. `http::Request` for `http02::Request` if `http02` is a renamed `http` dependency.
+fn attribute_to_package(packages: &BTreeMap<String, String>, path: ModuleName) -> ModuleName {
+    if path.is_crate_local() {
+        return path;
+    }
+    let (name, rest) = match path.as_str().split_once("::") {
+        Some((name, rest)) => (name, Some(rest)),
+        None => (path.as_str(), None),
+    };
+    match (packages.get(name), rest) {
+        (Some(package), Some(rest)) if package != name => format!("{package}::{rest}").into(),
+        (Some(package), None) if package != name => package.as_str().into(),
+        _ => path,
+    }
+}
+
+/// Finds the closest module of the tree containing `path`, e.g. `crate::foo::bar` for
+/// `crate::foo::bar::baz` if `baz` is a function. Returns `None` for other crates.
+fn resolve_module(modules: &ModuleTree, path: &ModuleName) -> Option<ModuleName> {
+    if !path.is_crate_local() {
+        return None;
+    }
+    let mut current = Some(path.clone());
+    while let Some(module) = current {
+        if modules.contains_key(&module) {
+            return Some(module);
+        }
+        current = module.parent();
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::dependencies::{GetAllModuleFilesError, ModuleList, ModuleName, list_modules};
+
+    use super::ModuleGraph;
+
+    #[test]
+    fn resolves_items_to_their_module() {
+        let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/simple");
+        let graph = ModuleGraph::new(&list_modules(&crate_root).unwrap());
+        assert_eq!(
+            graph.resolve(&"crate::module_a::module_b::Foo".into()),
+            Some(ModuleName::from("crate::module_a::module_b"))
+        );
+        assert_eq!(
+            graph.resolve(&"crate::unknown".into()),
+            Some(ModuleName::from("crate"))
+        );
+        assert_eq!(graph.resolve(&"std::collections".into()), None);
+    }
+
+    #[test]
+    fn attributes_renamed_crates_to_their_package() {
+        let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/deps");
+        let crate_modules = list_modules(&crate_root).unwrap();
+        assert_eq!(crate_modules.packages["random"], "fastrand");
+        assert_eq!(crate_modules.packages["local_store"], "local-store");
+        let graph = ModuleGraph::new(&crate_modules);
+        let mut dependencies = graph
+            .raw_dependencies(&"crate::store".into())
+            .filter(|dependency| !dependency.is_crate_local())
+            .cloned()
+            .collect::<Vec<_>>();
+        dependencies.sort();
+        assert_eq!(
+            dependencies,
+            vec![ModuleName::from("serde"), ModuleName::from("serde_json")]
+        );
+    }
+
+    #[test]
+    fn lists_dependents() {
+        let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/broken");
+        let graph = ModuleGraph::new(&list_modules(&crate_root).unwrap());
+        assert_eq!(
+            graph
+                .dependents(&"crate::module_a".into())
+                .cloned()
+                .collect::<Vec<_>>(),
+            vec![
+                ModuleName::from("crate"),
+                ModuleName::from("crate::module_b")
+            ]
+        );
+    }
+
+    #[test]
+    fn computes_extraction_closure() {
+        let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/layered");
+        let graph = ModuleGraph::new(&list_modules(&crate_root).unwrap());
+        assert_eq!(
+            graph.extraction_closure(&["crate::util".into()]),
+            Ok(vec![ModuleName::from("crate::util")])
+        );
+        assert_eq!(
+            graph.extraction_closure(&["crate::api::handlers".into()]),
+            Ok(vec![
+                ModuleName::from("crate::api"),
+                ModuleName::from("crate::db"),
+                ModuleName::from("crate::util"),
+            ])
+        );
+    }
+
+    #[test]
+    fn fails_to_compute_closure_depending_on_crate_root() {
+        let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/pinned");
+        let graph = ModuleGraph::new(&list_modules(&crate_root).unwrap());
+        assert_eq!(
+            graph.extraction_closure(&["crate::server".into()]),
+            Err(GetAllModuleFilesError::NoClosedModuleSet(ModuleList(vec![
+                ModuleName::from("crate::settings")
+            ])))
+        );
+    }
+}
@@ -1,24 +1,61 @@
 #![allow(dead_code, unused_variables)]
 pub mod dependencies {
-    use cargo_toml::{Manifest, Package};
+    use cargo_toml::Manifest;
     use core::fmt;
     use itertools::join;
-    use std::collections::{HashMap, HashSet, VecDeque};
-    use std::fs::{self, create_dir_all, read_to_string};
+    use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+    use std::fs::read_to_string;
     use std::path::{Path, PathBuf};
 
     use proc_macro2::Span;
+    use serde::{Deserialize, Serialize};
     use syn::{
-        Ident, ItemMod, ItemUse, UseGlob, UseGroup, UseName, UsePath, UseRename, UseTree,
-        parse_file,
+        Ident, Item, ItemExternCrate, ItemMod, ItemUse, UseGlob, UseGroup, UseName, UsePath,
+        UseRename, UseTree, parse_file,
         spanned::Spanned,
         visit::{self, Visit},
     };
     use thiserror::Error;
 
-    #[derive(Debug, PartialEq, Eq, Clone, Hash)]
+    use crate::manifest::{PackageMetadata, TargetDependencies, target_crate_manifest};
+    use crate::transaction::Transaction;
+
+    #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Hash, Serialize, Deserialize)]
+    #[serde(transparent)]
     pub struct ModuleName(String);
 
+    impl ModuleName {
+        pub fn as_str(&self) -> &str {
+            &self.0
+        }
+
+        /// Whether the module is part of the current crate, i.e. starts with `crate`.
+        pub fn is_crate_local(&self) -> bool {
+            self.is_descendant_of(&ModuleName("crate".to_owned()))
+        }
+
+        /// Whether `self` is `ancestor` or one of its submodules.
+        pub fn is_descendant_of(&self, ancestor: &ModuleName) -> bool {
+            self.0 == ancestor.0
+                || self
+                    .0
+                    .strip_prefix(&ancestor.0)
+                    .is_some_and(|rest| rest.starts_with("::"))
+        }
+
+        /// The parent module, or `None` for a single-segment path like `crate`.
+        pub fn parent(&self) -> Option<ModuleName> {
+            self.0
+                .rsplit_once("::")
+                .map(|(parent, _)| ModuleName(parent.to_owned()))
+        }
+
+        /// Number of modules between the crate root and `self`.
+        pub fn depth(&self) -> usize {
+            self.0.matches("::").count()
+        }
+    }
+
     impl From<String> for ModuleName {
         fn from(value: String) -> Self {
             Self(value)
@@ -51,7 +88,14 @@ pub mod dependencies {
     }
 
     /// A module path like `foo.bar`
+    #[derive(Debug, Clone, PartialEq, Eq)]
     pub struct ModulePath(String);
+    impl From<&ModuleName> for ModulePath {
+        fn from(value: &ModuleName) -> Self {
+            let path = value.0.strip_prefix("crate").unwrap_or(&value.0);
+            Self(path.trim_start_matches("::").replace("::", "."))
+        }
+    }
     impl From<String> for ModulePath {
         fn from(value: String) -> Self {
             Self(value)
@@ -120,17 +164,113 @@ pub mod dependencies {
         statement: UseStatementDetail,
     }
 
+    impl UseStatement {
+        pub fn source_module(&self) -> &ModuleName {
+            &self.source_module
+        }
+
+        /// Span of the whole `use` item, attributes included.
+        pub fn span(&self) -> Span {
+            self.statement.span
+        }
+    }
+
     pub type UseStatements = Vec<UseStatement>;
 
     #[derive(Debug, Hash, PartialEq, Eq)]
     pub struct File(String);
 
+    impl File {
+        /// Path of the file, relative to the crate root.
+        pub fn path(&self) -> &Path {
+            Path::new(&self.0)
+        }
+    }
+
     pub type UseStatementMap = HashMap<File, UseStatements>;
 
+    /// Number of items declared directly in a module, by kind.
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+    pub struct ItemCounts {
+        /// `fn`
+        pub functions: usize,
+        /// `struct`, `enum`, `union` and `type`
+        pub types: usize,
+        /// `trait` and trait aliases
+        pub traits: usize,
+        /// `impl` blocks
+        pub impls: usize,
+        /// `const` and `static`
+        pub constants: usize,
+        /// Macro definitions and invocations
+        pub macros: usize,
+    }
+
+    impl ItemCounts {
+        fn from_items(items: &[Item]) -> Self {
+            let mut counts = Self::default();
+            for item in items {
+                match item {
+                    Item::Fn(_) => counts.functions += 1,
+                    Item::Struct(_) | Item::Enum(_) | Item::Union(_) | Item::Type(_) => {
+                        counts.types += 1
+                    }
+                    Item::Trait(_) | Item::TraitAlias(_) => counts.traits += 1,
+                    Item::Impl(_) => counts.impls += 1,
+                    Item::Const(_) | Item::Static(_) => counts.constants += 1,
+                    Item::Macro(_) => counts.macros += 1,
+                    _ => {}
+                }
+            }
+            counts
+        }
+
+        pub fn total(&self) -> usize {
+            self.functions + self.types + self.traits + self.impls + self.constants + self.macros
+        }
+    }
+
+    /// A module found while walking the crate.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ModuleInfo {
+        /// File containing the module, relative to the crate root.
+        pub file: PathBuf,
+        /// Whether the module is declared inline (`mod foo { … }`) in `file`.
+        pub inline: bool,
+        /// Number of lines in the module, excluding its inline submodules.
+        pub lines: usize,
+        /// Items declared directly in the module.
+        pub items: ItemCounts,
+    }
+
+    /// All the modules of a crate, including the crate root.
+    pub type ModuleTree = BTreeMap<ModuleName, ModuleInfo>;
+
+    /// The result of walking a crate: its module tree and the `use` statements in each file.
+    #[derive(Debug)]
+    pub struct CrateModules {
+        pub modules: ModuleTree,
+        pub use_statements: UseStatementMap,
+        /// Packages of the other crates, by the name the code refers to them with, e.g. `http`
+        /// for `http02` with `http02 = { package = "http", version = "0.2" }`.
+        pub packages: BTreeMap<String, String>,
+        /// Aliases of `extern crate foo as bar;` items of the crate root, to the name they alias,
+        /// e.g. `bar` to `foo`. They can be used anywhere in the crate.
+        pub crate_aliases: BTreeMap<String, String>,
+    }
+
     #[derive(Debug)]
     enum ModStatement {
-        External { ident: Ident, span: Span },
-        Inline { ident: Ident, span: Span },
+        External {
+            ident: Ident,
+            span: Span,
+            /// Path of the module declaring it, including the inline modules in its file.
+            ancestors: Vec<String>,
+        },
+        Inline {
+            ident: Ident,
+            span: Span,
+        },
     }
 
     #[derive(Debug)]
@@ -139,14 +279,36 @@ pub mod dependencies {
         span: Span,
     }
 
+    #[derive(Debug)]
+    struct InlineModule {
+        name: ModuleName,
+        lines: usize,
+        items: ItemCounts,
+    }
+
     #[derive(Debug)]
     struct Visitor {
         use_statements: Vec<UseStatement>,
         mod_statements: Vec<ModStatement>,
+        inline_modules: Vec<InlineModule>,
+        /// `extern crate` items with an alias in the crate root, by alias.
+        crate_aliases: BTreeMap<String, String>,
         /// Stack of module identifiers from the crate root through both file-based (`mod foo;`) and inline (`mod bar { … }`) modules
         ancestors: Vec<String>,
     }
 
+    fn module_name_from_ancestors(ancestors: &[String]) -> ModuleName {
+        std::iter::once("crate".to_string())
+            .chain(ancestors.iter().cloned())
+            .collect::<Vec<_>>()
+            .join("::")
+            .into()
+    }
+
+    fn span_lines(span: Span) -> usize {
+        span.end().line - span.start().line + 1
+    }
+
     #[derive(Debug)]
     struct FileToVisit {
         file: PathBuf,
@@ -158,6 +320,8 @@ pub mod dependencies {
             Self {
                 use_statements: Vec::new(),
                 mod_statements: Vec::new(),
+                inline_modules: Vec::new(),
+                crate_aliases: BTreeMap::new(),
                 ancestors: ancestors.to_owned(),
             }
         }
@@ -182,23 +346,38 @@ pub mod dependencies {
                 self.mod_statements.push(ModStatement::External {
                     span: node.span(),
                     ident: node.ident.to_owned(),
+                    ancestors: self.ancestors.clone(),
                 });
             }
             self.ancestors.push(node.ident.to_string());
+            if let Some((_, items)) = &node.content {
+                self.inline_modules.push(InlineModule {
+                    name: module_name_from_ancestors(&self.ancestors),
+                    lines: span_lines(node.span()),
+                    items: ItemCounts::from_items(items),
+                });
+            }
             visit::visit_item_mod(self, node);
 
             self.ancestors.pop();
         }
 
+        fn visit_item_extern_crate(&mut self, node: &'ast ItemExternCrate) {
+            // `extern crate self as name;` refers to the crate itself.
+            if let Some((_, alias)) = &node.rename
+                && self.ancestors.is_empty()
+                && node.ident != "self"
+            {
+                self.crate_aliases
+                    .insert(alias.to_string(), node.ident.to_string());
+            }
+        }
+
         fn visit_item_use(&mut self, node: &'ast ItemUse) {
             let items = flatten_use_tree(&self.ancestors, &[], &node.tree);
 
-            let path_segments = std::iter::once("crate".to_string())
-                .chain(self.ancestors.iter().cloned())
-                .collect::<Vec<_>>();
-
             self.use_statements.push(UseStatement {
-                source_module: path_segments.join("::").into(),
+                source_module: module_name_from_ancestors(&self.ancestors),
                 target_modules: items
                     .iter()
                     .map(|item| item.get_module())
@@ -301,6 +480,59 @@ pub mod dependencies {
         EmptyModuleName,
     }
 
+    /// Packages of the dependencies of the crate at `crate_root`, by the name the code refers to
+    /// them with. Empty if its Cargo.toml can't be parsed.
+    fn dependency_packages(crate_root: &Path) -> BTreeMap<String, String> {
+        let Some(manifest) = read_to_string(crate_root.join("Cargo.toml"))
+            .ok()
+            .and_then(|cargo_toml| Manifest::from_str(&cargo_toml).ok())
+        else {
+            return BTreeMap::new();
+        };
+        let tables = [
+            &manifest.dependencies,
+            &manifest.dev_dependencies,
+            &manifest.build_dependencies,
+        ]
+        .into_iter()
+        .chain(manifest.target.values().flat_map(|target| {
+            [
+                &target.dependencies,
+                &target.dev_dependencies,
+                &target.build_dependencies,
+            ]
+        }));
+        tables
+            .flatten()
+            .map(|(key, dependency)| {
+                (
+                    key.replace('-', "_"),
+                    dependency.package().unwrap_or(key).to_owned(),
+                )
+            })
+            .collect()
+    }
+
+    /// Name of the crate as used in paths, e.g. `my_crate` for the `my-crate` package.
+    pub fn get_crate_name(crate_root: &Path) -> Result<String, ListUseStatementError> {
+        let cargo_toml = read_to_string(crate_root.join("Cargo.toml"))
+            .map_err(|_| ListUseStatementError::PathIsNotACrate)?;
+        let manifest =
+            Manifest::from_str(&cargo_toml).map_err(|_| ListUseStatementError::FileNotParsable)?;
+        let name = match manifest.package {
+            Some(package) => package.name,
+            None => crate_root
+                .canonicalize()
+                .ok()
+                .and_then(|root| {
+                    root.file_name()
+                        .map(|name| name.to_string_lossy().to_string())
+                })
+                .ok_or(ListUseStatementError::PathIsNotACrate)?,
+        };
+        Ok(name.replace('-', "_"))
+    }
+
     fn get_crate_entrypoint(crate_root: &Path) -> Result<PathBuf, ListUseStatementError> {
         // TODO: support multiple targets and custom paths different than src/main.rs or src/lib.rs
 
@@ -351,9 +583,16 @@ pub mod dependencies {
     pub fn list_use_statements(
         crate_root: &Path,
     ) -> Result<UseStatementMap, ListUseStatementError> {
+        list_modules(crate_root).map(|crate_modules| crate_modules.use_statements)
+    }
+
+    /// Walk the crate from its entrypoint, listing its modules and their `use` statements.
+    pub fn list_modules(crate_root: &Path) -> Result<CrateModules, ListUseStatementError> {
         let mut files_visited = HashSet::new();
         let mut files_to_visit = VecDeque::new();
         let mut use_statement_map: UseStatementMap = HashMap::new();
+        let mut modules = ModuleTree::new();
+        let mut crate_aliases = BTreeMap::new();
         let entry_point = get_crate_entrypoint(crate_root)?;
         files_to_visit.push_back(FileToVisit {
             file: entry_point.clone(),
@@ -376,17 +615,18 @@ pub mod dependencies {
 
             let mut visitor = Visitor::new(&file_to_visit.module_ancestors);
             visitor.visit_file(&parsed_file);
+            crate_aliases.append(&mut visitor.crate_aliases);
 
             for mod_statement in visitor.mod_statements {
-                if let ModStatement::External { ident, span: _ } = mod_statement {
-                    let module_path = if file_to_visit.module_ancestors.is_empty() {
-                        ident.to_string()
-                    } else {
-                        format!("{}.{}", file_to_visit.module_ancestors.join("."), ident)
-                    };
-                    let file = mod_to_path(crate_root, &ModulePath(module_path))?;
-                    let mut new_ancestors = file_to_visit.module_ancestors.clone();
+                if let ModStatement::External {
+                    ident,
+                    span: _,
+                    ancestors,
+                } = mod_statement
+                {
+                    let mut new_ancestors = ancestors;
                     new_ancestors.push(ident.to_string());
+                    let file = mod_to_path(crate_root, &ModulePath(new_ancestors.join(".")))?;
                     files_to_visit.push_back(FileToVisit {
                         file,
                         module_ancestors: new_ancestors,
@@ -394,21 +634,62 @@ pub mod dependencies {
                 }
             }
 
+            let relative_file = file_to_visit
+                .file
+                .strip_prefix(crate_root)
+                .unwrap_or(&file_to_visit.file)
+                .to_path_buf();
+
+            let mut file_modules = vec![(
+                module_name_from_ancestors(&file_to_visit.module_ancestors),
+                ModuleInfo {
+                    file: relative_file.clone(),
+                    inline: false,
+                    lines: content.lines().count(),
+                    items: ItemCounts::from_items(&parsed_file.items),
+                },
+            )];
+            file_modules.extend(visitor.inline_modules.iter().map(|inline_module| {
+                (
+                    inline_module.name.clone(),
+                    ModuleInfo {
+                        file: relative_file.clone(),
+                        inline: true,
+                        lines: inline_module.lines,
+                        items: inline_module.items,
+                    },
+                )
+            }));
+            // Inline submodules are counted in their own entry, not in their parent's.
+            for inline_module in &visitor.inline_modules {
+                let parent = inline_module.name.parent();
+                if let Some((_, parent_info)) = file_modules
+                    .iter_mut()
+                    .find(|(name, _)| Some(name) == parent.as_ref())
+                {
+                    parent_info.lines = parent_info.lines.saturating_sub(inline_module.lines);
+                }
+            }
+            modules.extend(file_modules);
+
             use_statement_map.insert(
-                File(
-                    file_to_visit
-                        .file
-                        .strip_prefix(crate_root)
-                        .unwrap_or(&file_to_visit.file)
-                        .to_string_lossy()
-                        .to_string(),
-                ),
+                File(relative_file.to_string_lossy().to_string()),
                 visitor.use_statements,
             );
             files_visited.insert(file_to_visit.file);
         }
 
-        Ok(use_statement_map)
+        let mut packages = dependency_packages(crate_root);
+        for (alias, name) in &crate_aliases {
+            let package = packages.get(name).unwrap_or(name).clone();
+            packages.insert(alias.clone(), package);
+        }
+        Ok(CrateModules {
+            modules,
+            use_statements: use_statement_map,
+            packages,
+            crate_aliases,
+        })
     }
 
     pub type ModuleDependencies = HashMap<ModuleName, HashSet<ModuleName>>;
@@ -443,87 +724,119 @@ pub mod dependencies {
         InvalidCargoToml,
     }
 
-    /// Creates a new crate named [target_crate_name] at the [target_crate_root]
+    /// Creates a new crate named [target_crate_name] at the [target_crate_root], with the
+    /// given `lib.rs` content (a placeholder comment if empty).
     pub fn create_target_crate(
         target_crate_root: &std::path::Path,
         target_crate_name: &str,
+        lib_rs: &str,
     ) -> Result<(), CreateCrateError> {
-        create_dir_all(target_crate_root.join("src")).map_err(|_| CreateCrateError::InvalidPath)?;
-
-        let manifest = Manifest {
-            package: Some(Package::<()>::new(target_crate_name.to_owned(), "0.0.1")),
-            ..Default::default()
-        };
-
-        fs::write(
-            target_crate_root.join("Cargo.toml"),
-            toml::to_string_pretty(&manifest).map_err(|_| CreateCrateError::InvalidCargoToml)?,
+        let manifest = target_crate_manifest(
+            target_crate_name,
+            &PackageMetadata::default(),
+            &TargetDependencies::default(),
         )
-        .map_err(CreateCrateError::FailedToCreateCrate)?;
-
-        fs::write(
-            target_crate_root.join("src/lib.rs"),
-            "// your new library crate\n",
-        )
-        .map_err(CreateCrateError::FailedToCreateCrate)?;
+        .map_err(|_| CreateCrateError::InvalidCargoToml)?;
+        Transaction::run(|transaction| {
+            transaction
+                .create_dir_all(&target_crate_root.join("src"))
+                .map_err(|_| CreateCrateError::InvalidPath)?;
+            transaction
+                .write(&target_crate_root.join("Cargo.toml"), manifest)
+                .map_err(CreateCrateError::FailedToCreateCrate)?;
+            transaction
+                .write(
+                    &target_crate_root.join("src/lib.rs"),
+                    target_crate_lib_rs(lib_rs),
+                )
+                .map_err(CreateCrateError::FailedToCreateCrate)
+        })?;
         Ok(())
     }
 
+    /// Content of the `lib.rs` of a new crate, a placeholder comment if `lib_rs` is empty.
+    pub fn target_crate_lib_rs(lib_rs: &str) -> &str {
+        if lib_rs.is_empty() {
+            "// your new library crate\n"
+        } else {
+            lib_rs
+        }
+    }
+
     #[derive(Error, Debug, PartialEq)]
     pub enum GetAllModuleFilesError {
         #[error("empty module name")]
         EmptyModuleName,
-        #[error("failed to convert module to path {0}")]
-        ModulePathError(ListUseStatementError),
+        #[error("module not found in the crate: {0}")]
+        ModuleNotFound(ModuleName),
         #[error(
             "module is not self contained - imports non-descendants modules from the same crate: {0}"
         )]
         ModuleIsNotSelfContained(ModuleList),
-        #[error("invalid parent directory")]
-        InvalidParentDirectory,
+        #[error(
+            "no set of modules can be extracted without the crate root, which is imported by: {0}"
+        )]
+        NoClosedModuleSet(ModuleList),
     }
 
-    /// Returns all the files in the create that are dependencies of `module`
-    pub fn get_all_module_files(
-        crate_root: &Path,
-        module: &ModulePath,
+    /// Checks that `modules` and their submodules only import from each other or from other
+    /// crates.
+    pub fn check_self_contained(
+        modules: &[ModuleName],
         use_statements: &UseStatementMap,
-    ) -> Result<PathBuf, GetAllModuleFilesError> {
-        let parts = module.0.split('.').collect::<Vec<_>>();
-        let module_name = parts
-            .split_last()
-            .ok_or(GetAllModuleFilesError::EmptyModuleName)?
-            .0;
+    ) -> Result<(), GetAllModuleFilesError> {
+        let is_extracted =
+            |module: &ModuleName| modules.iter().any(|root| module.is_descendant_of(root));
+        let all_dependencies = list_dependencies(use_statements);
+        let mut non_descendant_dependencies: Vec<ModuleName> = all_dependencies
+            .iter()
+            .filter(|(source, _)| is_extracted(source))
+            .flat_map(|(_, dependencies)| dependencies)
+            .filter(|dependency| dependency.is_crate_local() && !is_extracted(dependency))
+            .cloned()
+            .collect::<HashSet<_>>()
+            .into_iter()
+            .collect();
+        if non_descendant_dependencies.is_empty() {
+            return Ok(());
+        }
+        non_descendant_dependencies.sort();
+        Err(GetAllModuleFilesError::ModuleIsNotSelfContained(
+            ModuleList(non_descendant_dependencies),
+        ))
+    }
 
-        let mut root_path = crate_root.join("src");
-        root_path.extend(parts);
+    /// Returns all the files in the crate that are dependencies of `module`
+    pub fn get_all_module_files(
+        crate_modules: &CrateModules,
+        module: &ModulePath,
+    ) -> Result<Vec<PathBuf>, GetAllModuleFilesError> {
+        check_self_contained(&[module.into()], &crate_modules.use_statements)?;
+        get_module_files(&crate_modules.modules, module)
+    }
 
-        let file_path =
-            mod_to_path(crate_root, module).map_err(GetAllModuleFilesError::ModulePathError)?;
-        let all_dependencies = list_dependencies(use_statements);
-        let module_name: ModuleName = module.into();
-        if let Some(file_dependencies) = all_dependencies.get(&module_name) {
-            let non_descendant_dependencies: Vec<ModuleName> = file_dependencies
-                .iter()
-                .filter(|dependency| {
-                    dependency.0.starts_with("crate::") && !dependency.0.starts_with(&module_name.0)
-                })
-                .cloned()
-                .collect();
-            if !non_descendant_dependencies.is_empty() {
-                return Err(GetAllModuleFilesError::ModuleIsNotSelfContained(
-                    ModuleList(non_descendant_dependencies),
-                ));
-            }
+    /// Returns the files of `module` and of its submodules, relative to the crate root, parents
+    /// first.
+    ///
+    /// They come from the module tree rather than from the directory layout, so that both
+    /// `foo/mod.rs` and `foo.rs` with a `foo/` directory are supported. Inline modules have no
+    /// file of their own.
+    pub fn get_module_files(
+        modules: &ModuleTree,
+        module: &ModulePath,
+    ) -> Result<Vec<PathBuf>, GetAllModuleFilesError> {
+        if module.0.is_empty() {
+            return Err(GetAllModuleFilesError::EmptyModuleName);
         }
-        if file_path.file_name().and_then(|n| n.to_str()) == Some("mod.rs") {
-            let dir = file_path
-                .parent()
-                .ok_or(GetAllModuleFilesError::InvalidParentDirectory)?;
-            Ok(dir.to_path_buf())
-        } else {
-            Ok(file_path)
+        let module = ModuleName::from(module);
+        if !modules.contains_key(&module) {
+            return Err(GetAllModuleFilesError::ModuleNotFound(module));
         }
+        Ok(modules
+            .iter()
+            .filter(|(name, info)| name.is_descendant_of(&module) && !info.inline)
+            .map(|(_, info)| info.file.clone())
+            .collect())
     }
 
     #[cfg(test)]
@@ -540,12 +853,13 @@ pub mod dependencies {
         use syn::visit::Visit;
 
         use crate::dependencies::{
-            File, GetAllModuleFilesError, ListUseStatementError, ModuleList, ModuleName,
-            ModulePath, NormalizedUseStatement, UseStatement, UseStatementDetail, UseStatementType,
-            Visitor, get_all_module_files, list_dependencies, list_use_statements,
+            File, GetAllModuleFilesError, ItemCounts, ListUseStatementError, ModuleInfo,
+            ModuleList, ModuleName, ModulePath, NormalizedUseStatement, UseStatement,
+            UseStatementDetail, UseStatementType, Visitor, get_all_module_files, get_module_files,
+            list_dependencies, list_modules, list_use_statements,
         };
 
-        use super::{create_target_crate, mod_to_path};
+        use super::{check_self_contained, create_target_crate, get_crate_name, mod_to_path};
 
         #[test]
         fn builds_a_dependency_map() {
@@ -841,6 +1155,47 @@ pub mod dependencies {
             );
         }
 
+        #[test]
+        fn lists_inline_and_file_modules() {
+            let test_project = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/inline/");
+            let modules = list_modules(&test_project).unwrap().modules;
+            assert_eq!(
+                modules.into_iter().collect::<Vec<_>>(),
+                vec![
+                    (
+                        ModuleName::from("crate"),
+                        ModuleInfo {
+                            file: PathBuf::from("src/main.rs"),
+                            inline: false,
+                            lines: 2,
+                            items: ItemCounts {
+                                functions: 1,
+                                ..Default::default()
+                            },
+                        }
+                    ),
+                    (
+                        ModuleName::from("crate::module_a"),
+                        ModuleInfo {
+                            file: PathBuf::from("src/main.rs"),
+                            inline: true,
+                            lines: 3,
+                            items: ItemCounts::default(),
+                        }
+                    ),
+                    (
+                        ModuleName::from("crate::module_a::module_b"),
+                        ModuleInfo {
+                            file: PathBuf::from("src/main.rs"),
+                            inline: true,
+                            lines: 3,
+                            items: ItemCounts::default(),
+                        }
+                    ),
+                ]
+            );
+        }
+
         #[test]
         fn flattens_alias() {
             let src = "use crate::foo::Bar as Baz;";
@@ -994,25 +1349,61 @@ pub mod dependencies {
         #[test]
         fn finds_module_file() {
             let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/simple");
-            let statements = list_use_statements(&crate_root).unwrap();
+            let crate_modules = list_modules(&crate_root).unwrap();
 
             let module = ModulePath::from("module_a.module_b".to_string());
-            let file = get_all_module_files(&crate_root, &module, &statements).unwrap();
-
-            let rel: PathBuf = file.strip_prefix(&crate_root).unwrap().to_path_buf();
-            assert_eq!(rel, PathBuf::from("src/module_a/module_b.rs"));
+            let files = get_all_module_files(&crate_modules, &module).unwrap();
+            assert_eq!(files, vec![PathBuf::from("src/module_a/module_b.rs")]);
         }
 
         #[test]
-        fn finds_module_directory() {
+        fn finds_submodule_files() {
             let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/simple");
-            let statements = list_use_statements(&crate_root).unwrap();
+            let crate_modules = list_modules(&crate_root).unwrap();
 
             let module = ModulePath::from("module_a".to_string());
-            let file = get_all_module_files(&crate_root, &module, &statements).unwrap();
+            let files = get_all_module_files(&crate_modules, &module).unwrap();
+            assert_eq!(
+                files,
+                vec![
+                    PathBuf::from("src/module_a/mod.rs"),
+                    PathBuf::from("src/module_a/module_b.rs")
+                ]
+            );
+        }
+
+        #[test]
+        fn finds_files_of_inline_module_children() {
+            let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/lifted");
+            let crate_modules = list_modules(&crate_root).unwrap();
+
+            let files =
+                get_module_files(&crate_modules.modules, &ModulePath::from("auth")).unwrap();
+            assert_eq!(files, vec![PathBuf::from("src/auth/jwt.rs")]);
+        }
+
+        #[test]
+        fn finds_submodule_files_next_to_module_file() {
+            let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/modern");
+            let crate_modules = list_modules(&crate_root).unwrap();
 
-            let rel: PathBuf = file.strip_prefix(&crate_root).unwrap().to_path_buf();
-            assert_eq!(rel, PathBuf::from("src/module_a"));
+            let files =
+                get_module_files(&crate_modules.modules, &ModulePath::from("auth")).unwrap();
+            assert_eq!(
+                files,
+                vec![
+                    PathBuf::from("src/auth.rs"),
+                    PathBuf::from("src/auth/jwt.rs"),
+                    PathBuf::from("src/auth/session.rs"),
+                    PathBuf::from("src/auth/session/store.rs"),
+                ]
+            );
+            assert_eq!(
+                get_module_files(&crate_modules.modules, &ModulePath::from("missing")),
+                Err(GetAllModuleFilesError::ModuleNotFound(
+                    "crate::missing".into()
+                ))
+            );
         }
 
         #[test]
@@ -1021,7 +1412,7 @@ pub mod dependencies {
             let tmp_dir = temp_dir();
             let tmp_crate = tmp_dir.join(test_crate_name);
             remove_dir_all(&tmp_crate).unwrap_or_default();
-            create_target_crate(&tmp_crate, test_crate_name).unwrap();
+            create_target_crate(&tmp_crate, test_crate_name, "").unwrap();
             let paths = fs::read_dir(&tmp_crate).unwrap();
             let mut created_paths = paths
                 .map(|path| path.unwrap().file_name().to_string_lossy().into_owned())
@@ -1035,6 +1426,19 @@ pub mod dependencies {
             assert_eq!(created_src_paths, vec!["lib.rs"]);
         }
 
+        #[test]
+        fn gets_crate_name() {
+            let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+            assert_eq!(
+                get_crate_name(&fixtures.join("layered")),
+                Ok("layered".to_owned())
+            );
+            assert_eq!(
+                get_crate_name(&fixtures.join("simple")),
+                Ok("simple".to_owned())
+            );
+        }
+
         #[test]
         fn converts_module_path_to_module_name() {
             let module_path = ModulePath("module_a.module_b".into());
@@ -1060,12 +1464,8 @@ pub mod dependencies {
         #[test]
         fn fails_if_source_file_contains_non_descendants() {
             let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/broken");
-            let statements = list_use_statements(&crate_root).unwrap();
-            let res = get_all_module_files(
-                &crate_root,
-                &ModulePath("module_b".to_string()),
-                &statements,
-            );
+            let crate_modules = list_modules(&crate_root).unwrap();
+            let res = get_all_module_files(&crate_modules, &ModulePath("module_b".to_string()));
             assert_eq!(
                 res,
                 Err(GetAllModuleFilesError::ModuleIsNotSelfContained(
@@ -1074,6 +1474,25 @@ pub mod dependencies {
             )
         }
 
+        #[test]
+        fn checks_submodules_are_self_contained() {
+            let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/layered");
+            let statements = list_use_statements(&crate_root).unwrap();
+            assert_eq!(
+                check_self_contained(&["crate::api".into()], &statements),
+                Err(GetAllModuleFilesError::ModuleIsNotSelfContained(
+                    ModuleList(vec![
+                        ModuleName("crate::db".to_owned()),
+                        ModuleName("crate::util::format".to_owned()),
+                    ])
+                ))
+            );
+            assert_eq!(
+                check_self_contained(&["crate::util".into()], &statements),
+                Ok(())
+            );
+        }
+
         #[test]
         fn displays_module_list_as_comma_separated() {
             assert_eq!(
@@ -1088,27 +1507,373 @@ pub mod dependencies {
     }
 }
 
+pub mod edit;
+pub mod graph;
+pub mod manifest;
+pub mod metrics;
+pub mod partition;
+pub mod plan;
+pub mod rewrite;
+pub mod suggest;
+pub mod transaction;
+pub mod verify;
+
 pub mod refactor {
-    use std::{error::Error, path::Path};
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::error::Error;
+    use std::path::{Path, PathBuf};
 
-    use crate::dependencies::{
-        ModulePath, UseStatementMap, create_target_crate, get_all_module_files,
-    };
+    use thiserror::Error;
+
+    use crate::dependencies::{CrateModules, ModuleList, ModuleName, ModulePath, ModuleTree};
+    use crate::plan::{ExtractionOptions, plan_extraction};
+
+    #[derive(Debug, Error, PartialEq)]
+    pub enum ModuleMappingError {
+        #[error("no module to extract")]
+        NoModule,
+        #[error("modules not found in the crate: {0}")]
+        ModuleNotFound(ModuleList),
+        #[error("modules would end up at the same path in the new crate: {0}")]
+        ConflictingModules(ModuleList),
+    }
 
+    /// Where the extracted subtrees end up in the new crate.
+    ///
+    /// A single requested module becomes the root of the new crate, while several requested
+    /// modules each become a top-level module named after them. Subtrees pulled in as
+    /// dependencies keep their path relative to the crate root.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    pub struct ModuleMapping {
+        /// From the root of each subtree in the current crate to its path in the new crate.
+        roots: BTreeMap<ModuleName, ModuleName>,
+    }
+
+    impl ModuleMapping {
+        pub fn new(
+            requested: &[ModuleName],
+            roots: &[ModuleName],
+            modules: &ModuleTree,
+        ) -> Result<Self, ModuleMappingError> {
+            if requested.is_empty() {
+                return Err(ModuleMappingError::NoModule);
+            }
+            let missing = requested
+                .iter()
+                .filter(|module| !modules.contains_key(module))
+                .cloned()
+                .collect::<Vec<_>>();
+            if !missing.is_empty() {
+                return Err(ModuleMappingError::ModuleNotFound(ModuleList(missing)));
+            }
+            let roots = roots
+                .iter()
+                .filter(|root| {
+                    !roots
+                        .iter()
+                        .any(|other| other != *root && root.is_descendant_of(other))
+                })
+                .map(|root| {
+                    let new_root = if !requested.contains(root) {
+                        root.clone()
+                    } else if requested.len() == 1 {
+                        ModuleName::from("crate")
+                    } else {
+                        let name = root.as_str().rsplit("::").next().unwrap_or_default();
+                        ModuleName::from(format!("crate::{name}"))
+                    };
+                    (root.clone(), new_root)
+                })
+                .collect();
+            let mapping = Self { roots };
+            mapping.check_conflicts(modules)?;
+            Ok(mapping)
+        }
+
+        fn check_conflicts(&self, modules: &ModuleTree) -> Result<(), ModuleMappingError> {
+            let mut new_modules: BTreeMap<ModuleName, Vec<ModuleName>> = BTreeMap::new();
+            for module in modules.keys() {
+                if let Some(new_module) = self.map(module) {
+                    new_modules
+                        .entry(new_module)
+                        .or_default()
+                        .push(module.clone());
+                }
+            }
+            let mut conflicts = new_modules
+                .into_iter()
+                .filter(|(_, old_modules)| old_modules.len() > 1)
+                .flat_map(|(_, old_modules)| old_modules)
+                .collect::<Vec<_>>();
+            // Modules generated to hold nested subtrees can't replace extracted ones either.
+            for intermediate in self.intermediate_modules() {
+                conflicts.extend(
+                    modules
+                        .keys()
+                        .filter(|module| self.map(module).as_ref() == Some(&intermediate))
+                        .cloned(),
+                );
+            }
+            if conflicts.is_empty() {
+                return Ok(());
+            }
+            conflicts.sort();
+            conflicts.dedup();
+            Err(ModuleMappingError::ConflictingModules(ModuleList(
+                conflicts,
+            )))
+        }
+
+        /// The roots of the extracted subtrees, with their path in the new crate.
+        pub fn roots(&self) -> impl Iterator<Item = (&ModuleName, &ModuleName)> {
+            self.roots.iter()
+        }
+
+        /// Translates a path of the current crate to the new crate, if it is extracted.
+        pub fn map(&self, path: &ModuleName) -> Option<ModuleName> {
+            let (root, new_root) = self
+                .roots
+                .iter()
+                .filter(|(root, _)| path.is_descendant_of(root))
+                .max_by_key(|(root, _)| root.depth())?;
+            let suffix = &path.as_str()[root.as_str().len()..];
+            Some(ModuleName::from(format!("{new_root}{suffix}")))
+        }
+
+        /// Modules of the new crate that only exist to hold a nested subtree, e.g. `crate::util`
+        /// for `crate::util::strings` pulled in as a dependency.
+        fn intermediate_modules(&self) -> BTreeSet<ModuleName> {
+            self.roots
+                .values()
+                .flat_map(|new_root| std::iter::successors(new_root.parent(), ModuleName::parent))
+                .filter(|module| module.parent().is_some())
+                .filter(|module| !self.roots.values().any(|new_root| new_root == module))
+                .collect()
+        }
+
+        /// Declarations of the extracted modules to put in the `lib.rs` of the new crate.
+        pub fn lib_rs_declarations(&self) -> String {
+            fn declare(
+                module: &ModuleName,
+                new_roots: &BTreeSet<&ModuleName>,
+                intermediates: &BTreeSet<ModuleName>,
+                indent: usize,
+                out: &mut String,
+            ) {
+                let children = new_roots
+                    .iter()
+                    .copied()
+                    .chain(intermediates)
+                    .filter(|child| child.parent().as_ref() == Some(module))
+                    .collect::<BTreeSet<_>>();
+                for child in children {
+                    let name = child.as_str().rsplit("::").next().unwrap_or_default();
+                    let padding = "    ".repeat(indent);
+                    if new_roots.contains(child) {
+                        out.push_str(&format!("{padding}pub mod {name};\n"));
+                    } else {
+                        out.push_str(&format!("{padding}pub mod {name} {{\n"));
+                        declare(child, new_roots, intermediates, indent + 1, out);
+                        out.push_str(&format!("{padding}}}\n"));
+                    }
+                }
+            }
+
+            let new_roots = self.roots.values().collect::<BTreeSet<_>>();
+            let mut declarations = String::new();
+            declare(
+                &ModuleName::from("crate"),
+                &new_roots,
+                &self.intermediate_modules(),
+                0,
+                &mut declarations,
+            );
+            declarations
+        }
+    }
+
+    /// Extracts `modules` to a new crate, as `options` say. Returns the path of the journal to
+    /// undo it.
     pub fn extract_crate(
         crate_root: &Path,
-        module: &ModulePath,
+        modules: &[ModulePath],
         target_crate_name: &str,
         target_crate_root: &std::path::Path,
-        use_statements: &UseStatementMap,
-    ) -> Result<(), Box<dyn Error>> {
-        create_target_crate(target_crate_root, target_crate_name)?;
+        crate_modules: &CrateModules,
+        options: &ExtractionOptions,
+    ) -> Result<PathBuf, Box<dyn Error>> {
+        plan_extraction(
+            crate_root,
+            modules,
+            target_crate_name,
+            target_crate_root,
+            crate_modules,
+            options,
+        )?
+        .apply()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::path::{Path, PathBuf};
+
+        use pretty_assertions::assert_eq;
+
+        use crate::dependencies::{
+            ItemCounts, ModuleInfo, ModuleList, ModuleName, ModuleTree, list_modules,
+        };
+
+        use super::{ModuleMapping, ModuleMappingError};
+
+        fn layered_mapping(
+            requested: &[&str],
+            roots: &[&str],
+        ) -> Result<ModuleMapping, ModuleMappingError> {
+            let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/layered");
+            let modules = list_modules(&crate_root).unwrap().modules;
+            let to_names = |names: &[&str]| {
+                names
+                    .iter()
+                    .map(|name| ModuleName::from(*name))
+                    .collect::<Vec<_>>()
+            };
+            ModuleMapping::new(&to_names(requested), &to_names(roots), &modules)
+        }
+
+        #[test]
+        fn maps_single_module_to_crate_root() {
+            let mapping = layered_mapping(&["crate::api"], &["crate::api"]).unwrap();
+            assert_eq!(
+                mapping.map(&"crate::api::handlers::handle".into()),
+                Some("crate::handlers::handle".into())
+            );
+            assert_eq!(mapping.map(&"crate::db".into()), None);
+            assert_eq!(mapping.lib_rs_declarations(), "");
+        }
+
+        #[test]
+        fn maps_several_modules_to_top_level_modules() {
+            let mapping = layered_mapping(
+                &["crate::api::handlers", "crate::db"],
+                &["crate::api::handlers", "crate::db"],
+            )
+            .unwrap();
+            assert_eq!(
+                mapping.map(&"crate::api::handlers::handle".into()),
+                Some("crate::handlers::handle".into())
+            );
+            assert_eq!(
+                mapping.map(&"crate::db::Connection".into()),
+                Some("crate::db::Connection".into())
+            );
+            assert_eq!(
+                mapping.lib_rs_declarations(),
+                "pub mod db;\npub mod handlers;\n"
+            );
+        }
 
-        let path = get_all_module_files(crate_root, module, use_statements);
+        #[test]
+        fn keeps_dependencies_layout() {
+            let mapping = layered_mapping(
+                &["crate::api::handlers", "crate::util"],
+                &["crate::api::handlers", "crate::util", "crate::api"],
+            )
+            .unwrap();
+            assert_eq!(
+                mapping.map(&"crate::api::handlers".into()),
+                Some("crate::api::handlers".into())
+            );
+            assert_eq!(
+                mapping.lib_rs_declarations(),
+                "pub mod api;\npub mod util;\n"
+            );
+        }
+
+        #[test]
+        fn declares_intermediate_modules() {
+            let mapping =
+                layered_mapping(&["crate::db"], &["crate::db", "crate::api::handlers"]).unwrap();
+            assert_eq!(
+                mapping.lib_rs_declarations(),
+                "pub mod api {\n    pub mod handlers;\n}\n"
+            );
+        }
 
-        todo!();
-        // transform_statements_for_local_crate(file_to_move, &use_statements);
-        // cycle all the statement and updates files with the new crate reference
-        // update_statements_for_external_crate(files_to_move, use_statements);
+        #[test]
+        fn fails_on_unknown_modules() {
+            assert_eq!(
+                layered_mapping(
+                    &["crate::nope", "crate::db", "crate::crate::api"],
+                    &["crate::nope", "crate::db", "crate::crate::api"]
+                ),
+                Err(ModuleMappingError::ModuleNotFound(ModuleList(vec![
+                    "crate::nope".into(),
+                    "crate::crate::api".into()
+                ])))
+            );
+        }
+
+        #[test]
+        fn detects_conflicting_modules() {
+            let modules = [
+                "crate",
+                "crate::a",
+                "crate::a::log",
+                "crate::b",
+                "crate::b::log",
+                "crate::server",
+                "crate::server::util",
+                "crate::util",
+                "crate::util::strings",
+            ]
+            .into_iter()
+            .map(|module| {
+                (
+                    ModuleName::from(module),
+                    ModuleInfo {
+                        file: PathBuf::new(),
+                        inline: false,
+                        lines: 0,
+                        items: ItemCounts::default(),
+                    },
+                )
+            })
+            .collect::<ModuleTree>();
+            let mapping = |requested: &[&str], roots: &[&str]| {
+                let to_names = |names: &[&str]| {
+                    names
+                        .iter()
+                        .map(|name| ModuleName::from(*name))
+                        .collect::<Vec<_>>()
+                };
+                ModuleMapping::new(&to_names(requested), &to_names(roots), &modules)
+            };
+            assert_eq!(
+                mapping(
+                    &["crate::a::log", "crate::b::log"],
+                    &["crate::a::log", "crate::b::log"]
+                ),
+                Err(ModuleMappingError::ConflictingModules(ModuleList(vec![
+                    "crate::a::log".into(),
+                    "crate::b::log".into()
+                ])))
+            );
+            assert_eq!(
+                mapping(&["crate::server"], &["crate::server", "crate::util"]),
+                Err(ModuleMappingError::ConflictingModules(ModuleList(vec![
+                    "crate::server::util".into(),
+                    "crate::util".into()
+                ])))
+            );
+            assert_eq!(
+                mapping(
+                    &["crate::server"],
+                    &["crate::server", "crate::util::strings"]
+                ),
+                Err(ModuleMappingError::ConflictingModules(ModuleList(vec![
+                    "crate::server::util".into()
+                ])))
+            );
+        }
     }
 }
@@ -0,0 +1,2492 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::read_to_string;
+use std::path::{Component, Path, PathBuf};
+
+use cargo_toml::{
+    Dependency, DependencyDetail, DepsSet, InheritedDependencyDetail, Manifest, Target,
+    TargetDepsSet,
+};
+use serde::{Deserialize, Serialize};
+use syn::{
+    Attribute, Expr, ExprLit, Field, ImplItem, ItemExternCrate, ItemMod, ItemUse, Lit, Meta, Stmt,
+    Token, TraitItem, UseTree, Variant,
+    punctuated::Punctuated,
+    visit::{self, Visit},
+};
+use thiserror::Error;
+use toml_edit::{
+    Array, DocumentMut, InlineTable, Item, KeyMut, RawString, Table, Value, table, value,
+};
+
+use crate::dependencies::{CrateModules, ModuleName};
+use crate::edit::{SourceChange, TextEdit};
+use crate::refactor::ModuleMapping;
+use crate::rewrite::ancestors_of;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ManifestError {
+    #[error("file not readable: {0}")]
+    FileNotReadable(PathBuf),
+    #[error("file not parsable: {0}")]
+    FileNotParsable(PathBuf),
+    #[error("invalid manifest {0}: {1}")]
+    InvalidManifest(PathBuf, String),
+    #[error("failed to serialize the manifest of {0}: {1}")]
+    NotSerializable(String, String),
+    #[error("{0} already has a dependency named {1}")]
+    DependencyExists(PathBuf, String),
+}
+
+/// What the `cfg` attributes around some code require to compile it.
+#[derive(Debug, Default, Clone)]
+struct Gate {
+    /// Features of which one enables the code, if it depends on any.
+    features: Option<BTreeSet<String>>,
+    /// Whether the code is only compiled for tests.
+    test: bool,
+}
+
+/// Collects the names of the crates referred to in the extracted modules and in the ones that
+/// stay in the parent crate, and the features the `cfg` attributes of the extracted modules
+/// depend on.
+struct CrateNameVisitor<'a> {
+    mapping: &'a ModuleMapping,
+    ancestors: Vec<String>,
+    /// Names referred to by the extracted modules.
+    names: BTreeSet<String>,
+    /// Names referred to by the modules that stay in the parent crate.
+    parent_names: BTreeSet<String>,
+    /// Gates of the code being visited, innermost last.
+    gates: Vec<Gate>,
+    /// Gates of the declarations of the modules in their own file, by [ancestors_of] them.
+    module_gates: BTreeMap<Vec<String>, Gate>,
+    features: BTreeSet<String>,
+    /// Names only referred to by code enabled by features, with these features.
+    gated: BTreeMap<String, BTreeSet<String>>,
+    ungated: BTreeSet<String>,
+    /// Names referred to by code compiled outside of tests.
+    outside_tests: BTreeSet<String>,
+    /// Gates of the declarations of the extracted subtrees in the parent crate, which move along
+    /// with them.
+    declarations: Vec<Gate>,
+}
+
+impl CrateNameVisitor<'_> {
+    fn is_extracted(&self) -> bool {
+        let module = std::iter::once("crate")
+            .chain(self.ancestors.iter().map(String::as_str))
+            .collect::<Vec<_>>()
+            .join("::");
+        self.mapping.map(&ModuleName::from(module)).is_some()
+    }
+
+    fn add(&mut self, name: String) {
+        if matches!(name.as_str(), "crate" | "self" | "super" | "Self") {
+            return;
+        }
+        if !self.is_extracted() {
+            self.parent_names.insert(name);
+            return;
+        }
+        let gate = self.gates.last().cloned().unwrap_or_default();
+        match gate.features {
+            Some(features) => self.gated.entry(name.clone()).or_default().extend(features),
+            None => {
+                self.ungated.insert(name.clone());
+            }
+        }
+        if !gate.test {
+            self.outside_tests.insert(name.clone());
+        }
+        self.names.insert(name);
+    }
+
+    fn add_use_tree(&mut self, tree: &UseTree) {
+        match tree {
+            UseTree::Path(path) => self.add(path.ident.to_string()),
+            UseTree::Name(name) => self.add(name.ident.to_string()),
+            UseTree::Rename(rename) => self.add(rename.ident.to_string()),
+            UseTree::Group(group) => group.items.iter().for_each(|tree| self.add_use_tree(tree)),
+            UseTree::Glob(_) => {}
+        }
+    }
+
+    /// Adds the crates of the paths in `meta`, such as `derive(serde::Serialize)`.
+    fn add_meta(&mut self, meta: &Meta) {
+        if meta.path().segments.len() > 1 {
+            self.add(meta.path().segments[0].ident.to_string());
+        }
+        if let Meta::List(list) = meta
+            && let Ok(nested) =
+                list.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+        {
+            nested.iter().for_each(|meta| self.add_meta(meta));
+        }
+    }
+
+    /// Visits the code with the `cfg` `predicates` with `visit`, knowing what compiles it.
+    fn visit_gated<'p>(
+        &mut self,
+        predicates: impl IntoIterator<Item = &'p Meta>,
+        visit: impl FnOnce(&mut Self),
+    ) {
+        let mut gate = self.gates.last().cloned().unwrap_or_default();
+        for predicate in predicates {
+            let features = enabling_features(predicate);
+            if !features.is_empty() {
+                gate.features = Some(features);
+            }
+            gate.test |= requires_test(predicate);
+        }
+        self.gates.push(gate);
+        visit(self);
+        self.gates.pop();
+    }
+
+    /// Visits the code with the attributes `attrs` with `visit`, knowing what compiles it.
+    fn visit_attributed(&mut self, attrs: &[Attribute], visit: impl FnOnce(&mut Self)) {
+        let predicates = attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("cfg"))
+            .filter_map(|attr| attr.parse_args::<Meta>().ok())
+            .collect::<Vec<_>>();
+        self.visit_gated(&predicates, visit);
+    }
+}
+
+/// Whether the `cfg` predicate only holds for tests.
+fn requires_test(predicate: &Meta) -> bool {
+    match predicate {
+        Meta::Path(path) => path.is_ident("test"),
+        Meta::List(list) if list.path.is_ident("any") || list.path.is_ident("all") => {
+            let nested = list
+                .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+                .unwrap_or_default();
+            if list.path.is_ident("any") {
+                !nested.is_empty() && nested.iter().all(requires_test)
+            } else {
+                nested.iter().any(requires_test)
+            }
+        }
+        _ => false,
+    }
+}
+
+/// The features of which one is enough for the `cfg` predicate to hold, if it requires any.
+fn enabling_features(predicate: &Meta) -> BTreeSet<String> {
+    match predicate {
+        Meta::NameValue(name_value) if name_value.path.is_ident("feature") => {
+            match &name_value.value {
+                Expr::Lit(ExprLit {
+                    lit: Lit::Str(feature),
+                    ..
+                }) => BTreeSet::from([feature.value()]),
+                _ => BTreeSet::new(),
+            }
+        }
+        Meta::List(list) if list.path.is_ident("any") || list.path.is_ident("all") => {
+            let nested = list
+                .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+                .unwrap_or_default();
+            let mut nested = nested.iter().map(enabling_features);
+            if list.path.is_ident("any") {
+                // Every alternative has to enable the code.
+                let features = nested.collect::<Vec<_>>();
+                if features.iter().any(BTreeSet::is_empty) {
+                    return BTreeSet::new();
+                }
+                features.into_iter().flatten().collect()
+            } else {
+                // Any of the requirements does.
+                nested
+                    .find(|features| !features.is_empty())
+                    .unwrap_or_default()
+            }
+        }
+        // `not(feature = "x")` is enabled without any feature.
+        _ => BTreeSet::new(),
+    }
+}
+
+/// Adds the features the `cfg` predicate refers to, enabling or disabling code, to `features`.
+fn mentioned_features(predicate: &Meta, features: &mut BTreeSet<String>) {
+    match predicate {
+        Meta::NameValue(_) => features.extend(enabling_features(predicate)),
+        Meta::List(list) => {
+            if let Ok(nested) =
+                list.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+            {
+                nested
+                    .iter()
+                    .for_each(|predicate| mentioned_features(predicate, features));
+            }
+        }
+        Meta::Path(_) => {}
+    }
+}
+
+impl<'ast> Visit<'ast> for CrateNameVisitor<'_> {
+    fn visit_item(&mut self, node: &'ast syn::Item) {
+        let attrs = match node {
+            syn::Item::Const(item) => &item.attrs,
+            syn::Item::Enum(item) => &item.attrs,
+            syn::Item::ExternCrate(item) => &item.attrs,
+            syn::Item::Fn(item) => &item.attrs,
+            syn::Item::ForeignMod(item) => &item.attrs,
+            syn::Item::Impl(item) => &item.attrs,
+            syn::Item::Macro(item) => &item.attrs,
+            syn::Item::Mod(item) => &item.attrs,
+            syn::Item::Static(item) => &item.attrs,
+            syn::Item::Struct(item) => &item.attrs,
+            syn::Item::Trait(item) => &item.attrs,
+            syn::Item::TraitAlias(item) => &item.attrs,
+            syn::Item::Type(item) => &item.attrs,
+            syn::Item::Union(item) => &item.attrs,
+            syn::Item::Use(item) => &item.attrs,
+            _ => return visit::visit_item(self, node),
+        };
+        self.visit_attributed(attrs, |visitor| visit::visit_item(visitor, node));
+    }
+
+    fn visit_impl_item(&mut self, node: &'ast ImplItem) {
+        let attrs = match node {
+            ImplItem::Const(item) => &item.attrs,
+            ImplItem::Fn(item) => &item.attrs,
+            ImplItem::Type(item) => &item.attrs,
+            ImplItem::Macro(item) => &item.attrs,
+            _ => return visit::visit_impl_item(self, node),
+        };
+        self.visit_attributed(attrs, |visitor| visit::visit_impl_item(visitor, node));
+    }
+
+    fn visit_trait_item(&mut self, node: &'ast TraitItem) {
+        let attrs = match node {
+            TraitItem::Const(item) => &item.attrs,
+            TraitItem::Fn(item) => &item.attrs,
+            TraitItem::Type(item) => &item.attrs,
+            TraitItem::Macro(item) => &item.attrs,
+            _ => return visit::visit_trait_item(self, node),
+        };
+        self.visit_attributed(attrs, |visitor| visit::visit_trait_item(visitor, node));
+    }
+
+    fn visit_stmt(&mut self, node: &'ast Stmt) {
+        let attrs = match node {
+            Stmt::Local(local) => &local.attrs,
+            Stmt::Macro(statement) => &statement.attrs,
+            _ => return visit::visit_stmt(self, node),
+        };
+        self.visit_attributed(attrs, |visitor| visit::visit_stmt(visitor, node));
+    }
+
+    fn visit_field(&mut self, node: &'ast Field) {
+        self.visit_attributed(&node.attrs, |visitor| visit::visit_field(visitor, node));
+    }
+
+    fn visit_variant(&mut self, node: &'ast Variant) {
+        self.visit_attributed(&node.attrs, |visitor| visit::visit_variant(visitor, node));
+    }
+
+    fn visit_attribute(&mut self, node: &'ast Attribute) {
+        let is_extracted = self.is_extracted();
+        if node.path().is_ident("cfg")
+            && let Ok(predicate) = node.parse_args::<Meta>()
+        {
+            if is_extracted {
+                mentioned_features(&predicate, &mut self.features);
+            }
+        } else if node.path().is_ident("cfg_attr")
+            && let Ok(arguments) =
+                node.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+            && let Some((predicate, attributes)) =
+                arguments.iter().collect::<Vec<_>>().split_first()
+        {
+            if is_extracted {
+                mentioned_features(predicate, &mut self.features);
+            }
+            self.visit_gated([*predicate], |visitor| {
+                attributes.iter().for_each(|meta| visitor.add_meta(meta));
+            });
+        }
+    }
+
+    fn visit_item_mod(&mut self, node: &'ast ItemMod) {
+        let is_parent = !self.is_extracted();
+        self.ancestors.push(node.ident.to_string());
+        // The `cfg` of the declaration of an extracted subtree in the parent crate moves with it.
+        if is_parent && self.is_extracted() {
+            self.declarations
+                .push(self.gates.last().cloned().unwrap_or_default());
+            for predicate in node
+                .attrs
+                .iter()
+                .filter(|attr| attr.path().is_ident("cfg"))
+                .filter_map(|attr| attr.parse_args::<Meta>().ok())
+            {
+                mentioned_features(&predicate, &mut self.features);
+            }
+        }
+        if node.content.is_none() {
+            self.module_gates.insert(
+                self.ancestors.clone(),
+                self.gates.last().cloned().unwrap_or_default(),
+            );
+        }
+        visit::visit_item_mod(self, node);
+        self.ancestors.pop();
+    }
+
+    fn visit_item_use(&mut self, node: &'ast ItemUse) {
+        self.add_use_tree(&node.tree);
+    }
+
+    fn visit_item_extern_crate(&mut self, node: &'ast ItemExternCrate) {
+        self.add(node.ident.to_string());
+    }
+
+    fn visit_path(&mut self, node: &'ast syn::Path) {
+        // A single segment is a local item, a prelude item or a crate imported with `use`.
+        if node.leading_colon.is_some() || node.segments.len() > 1 {
+            self.add(node.segments[0].ident.to_string());
+        }
+        visit::visit_path(self, node);
+    }
+}
+
+/// How the code of the extracted modules depends on features.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct FeatureUsage {
+    /// Features that the `cfg` and `cfg_attr` attributes refer to.
+    pub features: BTreeSet<String>,
+    /// Names of crates, as in [CrateReferences::extracted], only referred to by code enabled by
+    /// features, with these features.
+    pub gated_crates: BTreeMap<String, BTreeSet<String>>,
+    /// Features of which one is enough to declare all the extracted modules in the parent crate,
+    /// if they require any.
+    pub declaration_features: Option<BTreeSet<String>>,
+    /// Whether all the extracted modules are only declared for tests.
+    pub declared_for_tests: bool,
+}
+
+/// What the code of the crate refers to, on both sides of the extraction.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CrateReferences {
+    /// The names by which the extracted modules refer to other crates, in `use` statements,
+    /// paths and `extern crate` items. They may also be names of local modules or types.
+    pub extracted: BTreeSet<String>,
+    /// The names of [CrateReferences::extracted] only referred to in code compiled for tests.
+    pub tests: BTreeSet<String>,
+    /// The names by which the modules that stay in the parent crate refer to other crates.
+    pub parent: BTreeSet<String>,
+    /// The features the extracted modules depend on.
+    pub features: FeatureUsage,
+}
+
+/// Finds what the code of the crate refers to, in a single pass over its files.
+pub fn crate_references(
+    crate_root: &Path,
+    crate_modules: &CrateModules,
+    mapping: &ModuleMapping,
+) -> Result<CrateReferences, ManifestError> {
+    let visitor = visit_crate(crate_root, crate_modules, mapping)?;
+    let mut gated_crates = visitor.gated;
+    gated_crates.retain(|name, _| !visitor.ungated.contains(name));
+    let declarations = visitor.declarations;
+    let declaration_features = declarations
+        .iter()
+        .map(|gate| gate.features.clone())
+        .collect::<Option<Vec<_>>>()
+        .filter(|features| !features.is_empty())
+        .map(|features| features.into_iter().flatten().collect());
+    Ok(CrateReferences {
+        tests: &visitor.names - &visitor.outside_tests,
+        extracted: visitor.names,
+        parent: visitor.parent_names,
+        features: FeatureUsage {
+            features: visitor.features,
+            gated_crates,
+            declaration_features,
+            declared_for_tests: !declarations.is_empty()
+                && declarations.iter().all(|gate| gate.test),
+        },
+    })
+}
+
+fn visit_crate<'a>(
+    crate_root: &Path,
+    crate_modules: &CrateModules,
+    mapping: &'a ModuleMapping,
+) -> Result<CrateNameVisitor<'a>, ManifestError> {
+    let mut visitor = CrateNameVisitor {
+        mapping,
+        ancestors: Vec::new(),
+        names: BTreeSet::new(),
+        parent_names: BTreeSet::new(),
+        gates: Vec::new(),
+        module_gates: BTreeMap::new(),
+        features: BTreeSet::new(),
+        gated: BTreeMap::new(),
+        ungated: BTreeSet::new(),
+        outside_tests: BTreeSet::new(),
+        declarations: Vec::new(),
+    };
+    for (module, info) in &crate_modules.modules {
+        // Inline modules are visited with the file of their parent.
+        if info.inline {
+            continue;
+        }
+        let content = read_to_string(crate_root.join(&info.file))
+            .map_err(|_| ManifestError::FileNotReadable(info.file.clone()))?;
+        let parsed_file = syn::parse_file(&content)
+            .map_err(|_| ManifestError::FileNotParsable(info.file.clone()))?;
+        visitor.ancestors = ancestors_of(module);
+        // Parent modules come first, so their declarations were visited already.
+        visitor.gates = Vec::from_iter(visitor.module_gates.get(&visitor.ancestors).cloned());
+        visitor.visit_file(&parsed_file);
+    }
+    Ok(visitor)
+}
+
+/// The dependencies of the new crate, and its features.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct TargetDependencies {
+    pub dependencies: DepsSet,
+    pub dev_dependencies: DepsSet,
+    /// Platform-specific dependencies, by `cfg()` or target triple as in the parent crate.
+    pub target: TargetDepsSet,
+    pub features: BTreeMap<String, Vec<String>>,
+}
+
+impl TargetDependencies {
+    /// The dependencies that aren't development ones, including the platform-specific ones.
+    fn normal_dependencies(&self) -> impl Iterator<Item = (&String, &Dependency)> {
+        self.dependencies.iter().chain(
+            self.target
+                .values()
+                .flat_map(|target| target.dependencies.iter()),
+        )
+    }
+}
+
+/// Copies the dependencies of the parent crate at `crate_root` that are referred to by `names`,
+/// for the new crate at `target_crate_root`.
+///
+/// Entries are copied as they are, with their version, features and source, except for relative
+/// paths which are rebased on the new crate. Dependencies only found in `[dev-dependencies]`, or
+/// only referred to by tests according to `test_names`, are development dependencies. The
+/// platform-specific tables are copied the same way.
+///
+/// `aliases` are the `extern crate foo as bar;` items of the parent's crate root, which stay
+/// there: a dependency referred to by such an alias is copied under the alias, renamed with
+/// `package`, unless it is inherited from the workspace, which can't be renamed.
+pub fn copy_dependencies(
+    crate_root: &Path,
+    target_crate_root: &Path,
+    names: &BTreeSet<String>,
+    test_names: &BTreeSet<String>,
+    aliases: &BTreeMap<String, String>,
+) -> Result<TargetDependencies, ManifestError> {
+    let manifest = read_manifest(crate_root)?;
+    let copy = |dependencies: &DepsSet| {
+        let mut copied = DepsSet::new();
+        for (key, dependency) in dependencies {
+            let name = key.replace('-', "_");
+            let dependency = rebase_dependency(dependency, crate_root, target_crate_root);
+            for alias in aliases
+                .iter()
+                .filter(|(alias, aliased)| **aliased == name && names.contains(*alias))
+                .map(|(alias, _)| alias)
+            {
+                let (key, dependency) = rename_dependency(key, &dependency, alias);
+                copied.entry(key).or_insert(dependency);
+            }
+            if names.contains(&name) {
+                copied.insert(key.clone(), dependency);
+            }
+        }
+        copied
+    };
+    let copy_table = |dependencies: &DepsSet, dev_dependencies: &DepsSet| {
+        let mut dependencies = copy(dependencies);
+        let mut dev_dependencies = copy(dev_dependencies);
+        // Development dependencies can't be optional.
+        let test_only = dependencies
+            .iter()
+            .filter(|(key, dependency)| {
+                test_names.contains(&key.replace('-', "_")) && !dependency.optional()
+            })
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in test_only {
+            if let Some(dependency) = dependencies.remove(&key) {
+                dev_dependencies.entry(key).or_insert(dependency);
+            }
+        }
+        dev_dependencies.retain(|key, _| !dependencies.contains_key(key));
+        (dependencies, dev_dependencies)
+    };
+    let (dependencies, dev_dependencies) =
+        copy_table(&manifest.dependencies, &manifest.dev_dependencies);
+    let target = manifest
+        .target
+        .iter()
+        .map(|(platform, target)| {
+            let (dependencies, dev_dependencies) =
+                copy_table(&target.dependencies, &target.dev_dependencies);
+            (
+                platform.clone(),
+                Target {
+                    dependencies,
+                    dev_dependencies,
+                    ..Default::default()
+                },
+            )
+        })
+        .filter(|(_, target)| {
+            !target.dependencies.is_empty() || !target.dev_dependencies.is_empty()
+        })
+        .collect();
+    Ok(TargetDependencies {
+        dependencies,
+        dev_dependencies,
+        target,
+        ..Default::default()
+    })
+}
+
+/// Parses the Cargo.toml in `directory`, without completing it from the files around it.
+fn read_manifest(directory: &Path) -> Result<Manifest, ManifestError> {
+    let path = directory.join("Cargo.toml");
+    let content =
+        read_to_string(&path).map_err(|_| ManifestError::FileNotReadable(path.clone()))?;
+    Manifest::from_str(&content)
+        .map_err(|error| ManifestError::InvalidManifest(path, error.to_string()))
+}
+
+/// `dependency` declared in the crate at `crate_root`, as declared in the crate at
+/// `target_crate_root`.
+fn rebase_dependency(
+    dependency: &Dependency,
+    crate_root: &Path,
+    target_crate_root: &Path,
+) -> Dependency {
+    let Dependency::Detailed(detail) = dependency else {
+        return dependency.clone();
+    };
+    let Some(path) = detail
+        .path
+        .as_deref()
+        .filter(|path| Path::new(path).is_relative())
+    else {
+        return dependency.clone();
+    };
+    let mut detail = detail.clone();
+    detail.path = Some(to_manifest_path(&relative_path(
+        target_crate_root,
+        &crate_root.join(path),
+    )));
+    Dependency::Detailed(detail)
+}
+
+/// The key and declaration of the dependency declared as `key` when referred to as `alias`.
+fn rename_dependency(key: &str, dependency: &Dependency, alias: &str) -> (String, Dependency) {
+    match dependency {
+        Dependency::Inherited(_) => (key.to_owned(), dependency.clone()),
+        Dependency::Simple(version) => (
+            alias.to_owned(),
+            Dependency::Detailed(Box::new(DependencyDetail {
+                version: Some(version.clone()),
+                package: Some(key.to_owned()),
+                ..Default::default()
+            })),
+        ),
+        Dependency::Detailed(detail) => {
+            let mut detail = detail.clone();
+            detail.package.get_or_insert_with(|| key.to_owned());
+            (alias.to_owned(), Dependency::Detailed(detail))
+        }
+    }
+}
+
+/// Dependencies moved to `[workspace.dependencies]`, so that the parent crate and the new crate
+/// share a single declaration of them.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct HoistedDependencies {
+    /// Entries to add to `[workspace.dependencies]`, with paths relative to the workspace root.
+    pub workspace_dependencies: DepsSet,
+    /// Dependencies of the parent crate to inherit from the workspace instead.
+    pub inherited: BTreeSet<String>,
+    /// Why some dependencies were not hoisted.
+    pub warnings: Vec<String>,
+}
+
+/// `dependency` declared in the crate at `crate_root`, as declared in `[workspace.dependencies]`
+/// at `workspace_root`. Whether a dependency is optional is up to each crate.
+fn workspace_dependency(
+    dependency: &Dependency,
+    crate_root: &Path,
+    workspace_root: &Path,
+) -> Option<DependencyDetail> {
+    let mut detail = match rebase_dependency(dependency, crate_root, workspace_root) {
+        Dependency::Simple(version) => DependencyDetail {
+            version: Some(version),
+            ..Default::default()
+        },
+        Dependency::Detailed(detail) => *detail,
+        Dependency::Inherited(_) => return None,
+    };
+    detail.optional = false;
+    detail.features.sort();
+    Some(detail)
+}
+
+/// `detail` as a version requirement if it has nothing else.
+fn simplify(detail: DependencyDetail) -> Dependency {
+    match &detail.version {
+        Some(version)
+            if detail
+                == (DependencyDetail {
+                    version: Some(version.clone()),
+                    ..Default::default()
+                }) =>
+        {
+            Dependency::Simple(version.clone())
+        }
+        _ => Dependency::Detailed(Box::new(detail)),
+    }
+}
+
+/// Moves the declarations of the `dependencies` of the new crate that the parent crate at
+/// `crate_root` still uses, according to `parent_names`, to `[workspace.dependencies]`. Both
+/// crates then inherit them.
+///
+/// A dependency is left as it is if it is declared differently by the parent crate in
+/// `[dependencies]` and `[dev-dependencies]`, or in the workspace already.
+pub fn hoist_shared_dependencies(
+    crate_root: &Path,
+    target_crate_root: &Path,
+    dependencies: &mut TargetDependencies,
+    parent_names: &BTreeSet<String>,
+) -> Result<HoistedDependencies, ManifestError> {
+    let mut hoisted = HoistedDependencies::default();
+    let parent = ManifestFile::load(crate_root, crate_root)?.document;
+    let workspace_root = find_workspace_root(crate_root, &parent)?
+        .filter(|root| !relative_path(root, target_crate_root).starts_with(".."));
+    let Some(workspace_root) = workspace_root else {
+        hoisted.warnings.push(format!(
+            "not hoisting dependencies: {} and {} are not in the same workspace",
+            crate_root.display(),
+            target_crate_root.display()
+        ));
+        return Ok(hoisted);
+    };
+    let parent = read_manifest(crate_root)?;
+    let workspace = read_manifest(&workspace_root)?
+        .workspace
+        .map(|workspace| workspace.dependencies)
+        .unwrap_or_default();
+
+    for target_dependencies in [
+        &mut dependencies.dependencies,
+        &mut dependencies.dev_dependencies,
+    ] {
+        for (name, dependency) in target_dependencies.iter_mut() {
+            if !parent_names.contains(&name.replace('-', "_")) {
+                continue;
+            }
+            // Already shared with the workspace.
+            if matches!(dependency, Dependency::Inherited(_)) {
+                continue;
+            }
+            let declarations = [&parent.dependencies, &parent.dev_dependencies]
+                .into_iter()
+                .filter_map(|parent_dependencies| parent_dependencies.get(name))
+                .filter_map(|dependency| {
+                    workspace_dependency(dependency, crate_root, &workspace_root)
+                })
+                .collect::<Vec<_>>();
+            let Some(declaration) = declarations.first() else {
+                continue;
+            };
+            if declarations.iter().any(|other| other != declaration) {
+                hoisted.warnings.push(format!(
+                    "not hoisting {name}: {} declares it differently in [dependencies] and \
+                     [dev-dependencies]",
+                    crate_root.display()
+                ));
+                continue;
+            }
+            match workspace.get(name) {
+                Some(existing)
+                    if workspace_dependency(existing, &workspace_root, &workspace_root)
+                        .as_ref()
+                        != Some(declaration) =>
+                {
+                    hoisted.warnings.push(format!(
+                        "not hoisting {name}: the workspace at {} already declares it differently",
+                        workspace_root.display()
+                    ));
+                    continue;
+                }
+                Some(_) => {}
+                None => {
+                    hoisted
+                        .workspace_dependencies
+                        .insert(name.clone(), simplify(declaration.clone()));
+                }
+            }
+            *dependency = Dependency::Inherited(InheritedDependencyDetail {
+                workspace: true,
+                optional: dependency.optional(),
+                ..Default::default()
+            });
+            hoisted.inherited.insert(name.clone());
+        }
+    }
+    Ok(hoisted)
+}
+
+/// Entries to add to the `[features]` of the parent crate, to enable the features of the new
+/// crate with the same name.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ForwardedFeatures {
+    pub features: BTreeMap<String, Vec<String>>,
+    /// How the parent crate depends on the new crate.
+    pub dependency: ParentDependency,
+    pub warnings: Vec<String>,
+}
+
+/// How the parent crate depends on the new crate, following how it declared the extracted
+/// modules.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ParentDependency {
+    #[default]
+    Normal,
+    /// The modules were only declared with some features, which enable the new crate.
+    Optional,
+    /// The modules were only declared for tests.
+    Development,
+}
+
+impl ForwardedFeatures {
+    fn forward(&mut self, feature: &str, entries: impl IntoIterator<Item = String>) {
+        let forwarded = self.features.entry(feature.to_owned()).or_default();
+        for entry in entries {
+            if !forwarded.contains(&entry) {
+                forwarded.push(entry);
+            }
+        }
+    }
+}
+
+/// Gives the new crate named `target_crate_name` the features its code depends on according to
+/// `usage`, enabled by the features of the parent crate at `crate_root` with the same name.
+///
+/// The features of the new crate enable what the ones of the parent crate enable among its
+/// `dependencies`, and the optional dependencies only used by the code they enable. An optional
+/// dependency still used without a feature keeps its implicit feature, enabled by the features of
+/// the parent crate that enable it.
+///
+/// When the parent crate only declared the extracted modules with some features, these features
+/// enable the new crate, which becomes optional. When it only declared them for tests, the new
+/// crate becomes a development dependency, whose features can't be forwarded.
+pub fn wire_features(
+    crate_root: &Path,
+    target_crate_name: &str,
+    dependencies: &mut TargetDependencies,
+    usage: &FeatureUsage,
+) -> Result<ForwardedFeatures, ManifestError> {
+    let parent = read_manifest(crate_root)?;
+    let mut forwarded = ForwardedFeatures::default();
+    let is_optional = |name: &str| {
+        dependencies
+            .normal_dependencies()
+            .any(|(key, dependency)| key == name && dependency.optional())
+    };
+    // Cargo only creates a feature for an optional dependency never referred to with `dep:`.
+    let explicit = parent
+        .features
+        .values()
+        .flatten()
+        .filter_map(|entry| entry.strip_prefix("dep:"))
+        .collect::<BTreeSet<_>>();
+    let is_implicit = |name: &str| {
+        !explicit.contains(name)
+            && !parent.features.contains_key(name)
+            && parent
+                .dependencies
+                .iter()
+                .chain(
+                    parent
+                        .target
+                        .values()
+                        .flat_map(|target| target.dependencies.iter()),
+                )
+                .any(|(key, dependency)| key == name && dependency.optional())
+    };
+    // What an entry of a feature of the parent crate becomes in the new crate, if it concerns it.
+    let target_entry = |entry: &str| {
+        if let Some(name) = entry.strip_prefix("dep:") {
+            is_optional(name).then(|| entry.to_owned())
+        } else if let Some((name, _)) = entry.split_once('/') {
+            let name = name.trim_end_matches('?');
+            dependencies
+                .normal_dependencies()
+                .any(|(key, _)| key == name)
+                .then(|| entry.to_owned())
+        } else if usage.features.contains(entry) {
+            Some(entry.to_owned())
+        } else {
+            (is_optional(entry) && is_implicit(entry)).then(|| format!("dep:{entry}"))
+        }
+    };
+
+    let mut features = BTreeMap::<String, Vec<String>>::new();
+    for feature in &usage.features {
+        let target_feature = format!("{target_crate_name}/{feature}");
+        let mut entries = Vec::new();
+        if let Some(parent_entries) = parent.features.get(feature) {
+            entries.extend(
+                parent_entries
+                    .iter()
+                    .filter_map(|entry| target_entry(entry)),
+            );
+            forwarded.forward(feature, [target_feature]);
+        } else if is_implicit(feature) {
+            // The implicit feature of the parent crate becomes explicit, to enable the one of
+            // the new crate too.
+            forwarded.forward(feature, [format!("dep:{feature}"), target_feature]);
+        } else {
+            forwarded.warnings.push(format!(
+                "{} has no feature {feature}, which the extracted modules depend on",
+                crate_root.display()
+            ));
+        }
+        features.insert(feature.clone(), entries);
+    }
+    for (name, enabling) in &usage.gated_crates {
+        let keys = dependencies
+            .normal_dependencies()
+            .filter(|(key, dependency)| key.replace('-', "_") == *name && dependency.optional())
+            .map(|(key, _)| key.clone());
+        for key in keys {
+            for feature in enabling {
+                let Some(entries) = features.get_mut(feature) else {
+                    continue;
+                };
+                let entry = format!("dep:{key}");
+                if !entries.contains(&entry) {
+                    entries.push(entry);
+                }
+            }
+        }
+    }
+    // The optional dependencies that no feature enables keep their implicit feature, which has
+    // to be enabled like the dependency is in the parent crate.
+    for (key, dependency) in dependencies.normal_dependencies() {
+        let enabled = features.values().flatten().any(|entry| {
+            entry.strip_prefix("dep:") == Some(key)
+                || entry.split_once('/').is_some_and(|(name, _)| name == key)
+        });
+        if !dependency.optional() || enabled {
+            continue;
+        }
+        let target_feature = format!("{target_crate_name}/{key}");
+        if is_implicit(key) {
+            forwarded.forward(key, [format!("dep:{key}"), target_feature.clone()]);
+        }
+        for (feature, entries) in &parent.features {
+            if entries.iter().any(|entry| {
+                entry == key
+                    || entry.strip_prefix("dep:") == Some(key)
+                    || entry.split_once('/').map(|(name, _)| name) == Some(key)
+            }) {
+                forwarded.forward(feature, [target_feature.clone()]);
+            }
+        }
+    }
+    dependencies.features = features;
+
+    let target_prefix = format!("{target_crate_name}/");
+    if usage.declared_for_tests {
+        forwarded.dependency = ParentDependency::Development;
+        let mut dropped = BTreeSet::new();
+        for entries in forwarded.features.values_mut() {
+            entries.retain(|entry| match entry.strip_prefix(&target_prefix) {
+                Some(feature) => {
+                    dropped.insert(feature.to_owned());
+                    false
+                }
+                None => true,
+            });
+        }
+        forwarded.features.retain(|_, entries| !entries.is_empty());
+        if !dropped.is_empty() {
+            forwarded.warnings.push(format!(
+                "{target_crate_name} is a development dependency of {}, so its features {} have \
+                 to be enabled in its declaration",
+                crate_root.display(),
+                dropped.into_iter().collect::<Vec<_>>().join(", ")
+            ));
+        }
+    } else if let Some(enabling) = &usage.declaration_features {
+        forwarded.dependency = ParentDependency::Optional;
+        for entries in forwarded.features.values_mut() {
+            for entry in entries {
+                if let Some(feature) = entry.strip_prefix(&target_prefix) {
+                    *entry = format!("{target_crate_name}?/{feature}");
+                }
+            }
+        }
+        for feature in enabling {
+            // Referring to the new crate with `dep:` would hide an implicit feature.
+            if is_implicit(feature) {
+                forwarded.forward(feature, [format!("dep:{feature}")]);
+            }
+            forwarded.forward(feature, [format!("dep:{target_crate_name}")]);
+        }
+    }
+    Ok(forwarded)
+}
+
+/// How to write the version requirements of the dependencies of the new crate, from the versions
+/// locked in Cargo.lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionPinning {
+    /// `=1.2.3`, only the locked version.
+    Exact,
+    /// `1.2.3`, the locked version or a newer compatible one.
+    Minimal,
+}
+
+#[derive(Deserialize)]
+struct Lockfile {
+    #[serde(default)]
+    package: Vec<LockedPackage>,
+}
+
+#[derive(Deserialize)]
+struct LockedPackage {
+    name: String,
+    version: String,
+    /// None for the crates of the workspace and the path dependencies.
+    source: Option<String>,
+}
+
+/// Checks the version requirements of the `dependencies` of the new crate at `target_crate_root`
+/// against the Cargo.lock of the parent crate at `crate_root`, and rewrites them with `pinning`
+/// if any. Returns warnings about the requirements that may resolve to other versions than the
+/// locked ones.
+///
+/// A new crate in the same workspace shares its Cargo.lock, where cargo keeps the locked version
+/// that matches a requirement. A new crate outside of it gets its own, resolved from scratch.
+pub fn pin_versions(
+    crate_root: &Path,
+    target_crate_root: &Path,
+    dependencies: &mut TargetDependencies,
+    pinning: Option<VersionPinning>,
+) -> Result<Vec<String>, ManifestError> {
+    let mut warnings = Vec::new();
+    let parent = ManifestFile::load(crate_root, crate_root)?.document;
+    let workspace_root = find_workspace_root(crate_root, &parent)?;
+    let shares_lockfile = workspace_root
+        .as_ref()
+        .is_some_and(|root| !relative_path(root, target_crate_root).starts_with(".."));
+    let path = workspace_root
+        .unwrap_or_else(|| crate_root.to_owned())
+        .join("Cargo.lock");
+    let Ok(content) = read_to_string(&path) else {
+        if pinning.is_some() {
+            warnings.push(format!(
+                "not pinning versions: {} doesn't exist",
+                path.display()
+            ));
+        }
+        return Ok(warnings);
+    };
+    let lockfile = toml::from_str::<Lockfile>(&content)
+        .map_err(|error| ManifestError::InvalidManifest(path.clone(), error.to_string()))?;
+
+    let tables = [
+        &mut dependencies.dependencies,
+        &mut dependencies.dev_dependencies,
+    ]
+    .into_iter()
+    .chain(
+        dependencies
+            .target
+            .values_mut()
+            .flat_map(|target| [&mut target.dependencies, &mut target.dev_dependencies]),
+    );
+    for table in tables {
+        for (key, dependency) in table.iter_mut() {
+            // Inherited dependencies are declared by the workspace, for both crates.
+            let (requirement, package) = match &*dependency {
+                Dependency::Simple(version) => (version.as_str(), key.as_str()),
+                Dependency::Detailed(detail) if detail.path.is_none() && detail.git.is_none() => {
+                    let Some(version) = &detail.version else {
+                        continue;
+                    };
+                    (version.as_str(), detail.package.as_deref().unwrap_or(key))
+                }
+                _ => continue,
+            };
+            let Ok(parsed) = semver::VersionReq::parse(requirement) else {
+                warnings.push(format!("{key}: invalid version requirement {requirement}"));
+                continue;
+            };
+            let locked = lockfile
+                .package
+                .iter()
+                .filter(|locked| locked.name == package && locked.source.is_some())
+                .filter_map(|locked| semver::Version::parse(&locked.version).ok())
+                .filter(|version| parsed.matches(version))
+                .collect::<Vec<_>>();
+            let version = match locked.as_slice() {
+                [] => {
+                    warnings.push(format!(
+                        "{key} = \"{requirement}\" matches no version locked in {}, cargo will \
+                         resolve it again",
+                        path.display()
+                    ));
+                    continue;
+                }
+                [version] => version,
+                _ => {
+                    warnings.push(format!(
+                        "{key} = \"{requirement}\" matches several versions locked in {}: {}",
+                        path.display(),
+                        itertools::join(&locked, ", ")
+                    ));
+                    continue;
+                }
+            };
+            let pinned = match pinning {
+                Some(VersionPinning::Exact) => format!("={version}"),
+                Some(VersionPinning::Minimal) => version.to_string(),
+                None => {
+                    let is_exact = matches!(
+                        parsed.comparators.as_slice(),
+                        [comparator] if comparator.op == semver::Op::Exact
+                            && comparator.patch.is_some()
+                            && comparator.pre == version.pre
+                    );
+                    if !shares_lockfile && !is_exact {
+                        warnings.push(format!(
+                            "{key} = \"{requirement}\" is locked to {version} in {}, but {} won't \
+                             share this Cargo.lock and may resolve it to a newer version",
+                            path.display(),
+                            target_crate_root.display()
+                        ));
+                    }
+                    continue;
+                }
+            };
+            match dependency {
+                Dependency::Simple(version) => *version = pinned,
+                Dependency::Detailed(detail) => detail.version = Some(pinned),
+                Dependency::Inherited(_) => {}
+            }
+        }
+    }
+    Ok(warnings)
+}
+
+/// Edits of the Cargo.toml files of the parent crate and of its workspace.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ManifestChanges {
+    pub changes: Vec<SourceChange>,
+    pub warnings: Vec<String>,
+}
+
+/// A Cargo.toml file being edited. Its comments and layout are kept.
+struct ManifestFile {
+    /// Relative to the root of the parent crate.
+    file: PathBuf,
+    content: String,
+    document: DocumentMut,
+}
+
+impl ManifestFile {
+    /// Loads the Cargo.toml in `directory`.
+    fn load(crate_root: &Path, directory: &Path) -> Result<Self, ManifestError> {
+        let path = directory.join("Cargo.toml");
+        let content =
+            read_to_string(&path).map_err(|_| ManifestError::FileNotReadable(path.clone()))?;
+        let document = parse_document(&path, &content)?;
+        let directory = relative_path(crate_root, directory);
+        Ok(Self {
+            file: if directory == Path::new(".") {
+                PathBuf::from("Cargo.toml")
+            } else {
+                directory.join("Cargo.toml")
+            },
+            content,
+            document,
+        })
+    }
+
+    /// The edit of the lines that changed, if any.
+    fn into_change(self) -> Option<SourceChange> {
+        let new_content = self.document.to_string();
+        let (old, new) = (self.content.as_bytes(), new_content.as_bytes());
+        let mut start = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+        if start == old.len() && start == new.len() {
+            return None;
+        }
+        // Whole lines read better in the diffs and in the verification notes.
+        start = self.content[..start]
+            .rfind('\n')
+            .map_or(0, |newline| newline + 1);
+        let suffix = old[start..]
+            .iter()
+            .rev()
+            .zip(new[start..].iter().rev())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let end = self.content.len() - suffix;
+        let suffix = if self.content[..end].ends_with('\n') {
+            suffix
+        } else {
+            self.content[end..]
+                .find('\n')
+                .map_or(0, |newline| suffix - newline - 1)
+        };
+        let mut change = SourceChange::new(self.file);
+        change.push(TextEdit::replace(
+            start..self.content.len() - suffix,
+            &new_content[start..new_content.len() - suffix],
+        ));
+        Some(change)
+    }
+}
+
+fn parse_document(path: &Path, content: &str) -> Result<DocumentMut, ManifestError> {
+    content
+        .parse::<DocumentMut>()
+        .map_err(|error| ManifestError::InvalidManifest(path.to_owned(), error.to_string()))
+}
+
+/// `dependency` as written in a Cargo.toml, as an inline table unless it is only a version, or
+/// as `dependency.workspace = true` if it is only inherited from the workspace.
+fn dependency_value(dependency: &Dependency) -> Result<Value, toml_edit::ser::Error> {
+    let mut value = dependency.serialize(toml_edit::ser::ValueSerializer::new())?;
+    if let Value::InlineTable(table) = &mut value
+        && table.len() == 1
+        && table.contains_key("workspace")
+    {
+        table.set_dotted(true);
+    }
+    Ok(value)
+}
+
+/// Moves the comments around the entry `key = item` to `new_value`, which replaces `item`.
+///
+/// The key of a dotted entry like `serde.workspace = true` is rendered from its last part, so the
+/// comments go there.
+fn keep_comments(mut key: KeyMut<'_>, item: &Item, new_value: &mut Value) {
+    let suffix = item
+        .as_value()
+        .and_then(|value| value.decor().suffix())
+        .and_then(RawString::as_str)
+        .map(str::to_owned);
+    if let Value::InlineTable(table) = new_value
+        && table.is_dotted()
+    {
+        let prefix = key
+            .leaf_decor()
+            .prefix()
+            .and_then(RawString::as_str)
+            .map(str::to_owned);
+        if let Some((mut inner_key, inner_value)) = table.iter_mut().last() {
+            if let Some(prefix) = prefix {
+                inner_key.leaf_decor_mut().set_prefix(prefix);
+            }
+            if let Some(suffix) = suffix {
+                inner_value.decor_mut().set_suffix(suffix);
+            }
+        }
+        key.leaf_decor_mut().clear();
+    } else if let Some(suffix) = suffix {
+        new_value.decor_mut().set_suffix(suffix);
+    }
+}
+
+/// `path` with forward slashes, as paths are written in Cargo.toml files.
+fn to_manifest_path(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+/// Whether one of the paths or globs of `patterns`, a workspace's `members` or
+/// `default-members`, matches `member`.
+fn is_listed(patterns: Option<&Item>, member: &str) -> bool {
+    let Some(patterns) = patterns.and_then(Item::as_array) else {
+        return false;
+    };
+    patterns
+        .iter()
+        .filter_map(Value::as_str)
+        .map(|pattern| pattern.trim_start_matches("./").trim_end_matches('/'))
+        .any(|pattern| {
+            pattern == member
+                || glob::Pattern::new(pattern).is_ok_and(|glob| {
+                    glob.matches_with(
+                        member,
+                        glob::MatchOptions {
+                            require_literal_separator: true,
+                            ..Default::default()
+                        },
+                    )
+                })
+        })
+}
+
+/// Appends `member` to the array `key` of `table`, such as the `members` of a workspace, creating
+/// it if needed. The new entry is laid out like the previous one, so that multi-line arrays stay
+/// multi-line.
+fn push_member(table: &mut Table, key: &str, member: &str) {
+    let item = table.entry(key).or_insert_with(|| value(Array::new()));
+    let Some(members) = item.as_array_mut() else {
+        *item = value(Array::from_iter([member]));
+        return;
+    };
+    let mut new_member = Value::from(member);
+    if let Some(last) = members.iter().last() {
+        let decor = last.decor().clone();
+        *new_member.decor_mut() = decor;
+        // The first entry of a single-line array isn't separated from the opening bracket.
+        if new_member
+            .decor()
+            .prefix()
+            .is_none_or(|prefix| prefix.as_str() == Some(""))
+        {
+            new_member.decor_mut().set_prefix(" ");
+        }
+    }
+    members.push_formatted(new_member);
+}
+
+/// The directory of the workspace the crate at `crate_root` belongs to, if any: the one its
+/// `package.workspace` points to, or else the closest one with a `[workspace]` table that lists
+/// the crate as a member.
+fn find_workspace_root(
+    crate_root: &Path,
+    manifest: &DocumentMut,
+) -> Result<Option<PathBuf>, ManifestError> {
+    if manifest.contains_key("workspace") {
+        return Ok(Some(crate_root.to_owned()));
+    }
+    if let Some(workspace) = manifest
+        .get("package")
+        .and_then(|package| package.get("workspace"))
+        .and_then(Item::as_str)
+    {
+        return Ok(Some(crate_root.join(workspace)));
+    }
+    for directory in normalize(crate_root).ancestors().skip(1) {
+        let path = directory.join("Cargo.toml");
+        if !path.is_file() {
+            continue;
+        }
+        let content =
+            read_to_string(&path).map_err(|_| ManifestError::FileNotReadable(path.clone()))?;
+        let document = parse_document(&path, &content)?;
+        if let Some(workspace) = document.get("workspace") {
+            let member = to_manifest_path(&relative_path(directory, crate_root));
+            // Cargo refuses to build a crate under a workspace that doesn't include it, unless
+            // it is excluded, in which case it is on its own.
+            return Ok(is_listed(workspace.get("members"), &member).then(|| directory.to_owned()));
+        }
+    }
+    Ok(None)
+}
+
+/// Edits the Cargo.toml of the parent crate at `crate_root` to depend on the new crate, and the
+/// one of its workspace to include the new crate in its `members`, and in its `default-members`
+/// if the parent crate is one of them. The `hoisted` dependencies are moved to the workspace,
+/// and the `forwarded` features are added to the parent crate, which depends on the new crate as
+/// they say.
+pub fn link_target_crate(
+    crate_root: &Path,
+    target_crate_name: &str,
+    target_crate_root: &Path,
+    hoisted: &HoistedDependencies,
+    forwarded: &ForwardedFeatures,
+) -> Result<ManifestChanges, ManifestError> {
+    let mut warnings = Vec::new();
+    let mut parent = ManifestFile::load(crate_root, crate_root)?;
+    // `my-crate` and `my_crate` are the same crate in the code.
+    let crate_name = target_crate_name.replace('-', "_");
+    for key in ["dependencies", "dev-dependencies"] {
+        if let Some((existing, _)) = parent
+            .document
+            .get(key)
+            .and_then(Item::as_table_like)
+            .and_then(|dependencies| {
+                dependencies
+                    .iter()
+                    .find(|(name, _)| name.replace('-', "_") == crate_name)
+            })
+        {
+            return Err(ManifestError::DependencyExists(
+                crate_root.join("Cargo.toml"),
+                existing.to_owned(),
+            ));
+        }
+    }
+    let invalid = |path: &Path, error: toml_edit::ser::Error| {
+        ManifestError::InvalidManifest(path.join("Cargo.toml"), error.to_string())
+    };
+    for key in ["dependencies", "dev-dependencies"] {
+        let Some(dependencies) = parent
+            .document
+            .get_mut(key)
+            .and_then(Item::as_table_like_mut)
+        else {
+            continue;
+        };
+        for name in &hoisted.inherited {
+            let Some((key, item)) = dependencies.get_key_value_mut(name) else {
+                continue;
+            };
+            let optional = item
+                .as_table_like()
+                .and_then(|dependency| dependency.get("optional"))
+                .and_then(Item::as_bool)
+                .unwrap_or_default();
+            let inherited = Dependency::Inherited(InheritedDependencyDetail {
+                workspace: true,
+                optional,
+                ..Default::default()
+            });
+            let mut inherited =
+                dependency_value(&inherited).map_err(|error| invalid(crate_root, error))?;
+            keep_comments(key, item, &mut inherited);
+            *item = value(inherited);
+        }
+    }
+    let mut dependency = InlineTable::new();
+    dependency.insert(
+        "path",
+        to_manifest_path(&relative_path(crate_root, target_crate_root)).into(),
+    );
+    if forwarded.dependency == ParentDependency::Optional {
+        dependency.insert("optional", true.into());
+    }
+    let key = match forwarded.dependency {
+        ParentDependency::Development => "dev-dependencies",
+        ParentDependency::Normal | ParentDependency::Optional => "dependencies",
+    };
+    let Some(dependencies) = parent
+        .document
+        .entry(key)
+        .or_insert_with(table)
+        .as_table_like_mut()
+    else {
+        return Err(ManifestError::InvalidManifest(
+            crate_root.join("Cargo.toml"),
+            format!("`{key}` is not a table"),
+        ));
+    };
+    dependencies.insert(target_crate_name, value(dependency));
+    if !forwarded.features.is_empty() {
+        let Some(features) = parent
+            .document
+            .entry("features")
+            .or_insert_with(table)
+            .as_table_mut()
+        else {
+            return Err(ManifestError::InvalidManifest(
+                crate_root.join("Cargo.toml"),
+                "`features` is not a table".to_owned(),
+            ));
+        };
+        for (feature, entries) in &forwarded.features {
+            for entry in entries {
+                let is_listed =
+                    features
+                        .get(feature)
+                        .and_then(Item::as_array)
+                        .is_some_and(|listed| {
+                            listed.iter().any(|listed| listed.as_str() == Some(entry))
+                        });
+                if !is_listed {
+                    push_member(features, feature, entry);
+                }
+            }
+        }
+    }
+
+    let mut workspace = None;
+    if let Some(workspace_root) = find_workspace_root(crate_root, &parent.document)? {
+        let member = relative_path(&workspace_root, target_crate_root);
+        let parent_member = to_manifest_path(&relative_path(&workspace_root, crate_root));
+        let manifest = if normalize(&workspace_root) == normalize(crate_root) {
+            &mut parent
+        } else {
+            workspace.insert(ManifestFile::load(crate_root, &workspace_root)?)
+        };
+        if member.starts_with("..") {
+            warnings.push(format!(
+                "{} is outside of the workspace at {}, it won't be a member of it",
+                target_crate_root.display(),
+                workspace_root.display()
+            ));
+        } else if let Some(table) = manifest
+            .document
+            .get_mut("workspace")
+            .and_then(Item::as_table_mut)
+        {
+            let member = to_manifest_path(&member);
+            if !is_listed(table.get("members"), &member) {
+                push_member(table, "members", &member);
+            }
+            if is_listed(table.get("default-members"), &parent_member)
+                && !is_listed(table.get("default-members"), &member)
+            {
+                push_member(table, "default-members", &member);
+            }
+            if !hoisted.workspace_dependencies.is_empty() {
+                let Some(dependencies) = table
+                    .entry("dependencies")
+                    .or_insert_with(toml_edit::table)
+                    .as_table_like_mut()
+                else {
+                    return Err(ManifestError::InvalidManifest(
+                        workspace_root.join("Cargo.toml"),
+                        "`workspace.dependencies` is not a table".to_owned(),
+                    ));
+                };
+                for (name, dependency) in &hoisted.workspace_dependencies {
+                    let dependency = dependency_value(dependency)
+                        .map_err(|error| invalid(&workspace_root, error))?;
+                    dependencies.insert(name, value(dependency));
+                }
+            }
+        }
+    }
+
+    let mut changes = Vec::new();
+    changes.extend(parent.into_change());
+    changes.extend(workspace.and_then(ManifestFile::into_change));
+    Ok(ManifestChanges { changes, warnings })
+}
+
+/// What the new crate shares with the parent crate and its workspace in its `[package]` and
+/// `[lints]` tables.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct PackageMetadata {
+    /// Fields of `[package]` inherited from the workspace like the parent crate does, such as
+    /// `edition` or `license`.
+    pub workspace_fields: Vec<String>,
+    /// The edition of the parent crate, unless it is inherited.
+    pub edition: Option<String>,
+    /// Whether the workspace defines `[workspace.lints]`.
+    pub workspace_lints: bool,
+}
+
+/// The metadata of the new crate at `target_crate_root`, from the one of the parent crate at
+/// `crate_root`. Fields are only inherited from the workspace if the new crate is part of it.
+pub fn package_metadata(
+    crate_root: &Path,
+    target_crate_root: &Path,
+) -> Result<PackageMetadata, ManifestError> {
+    let parent = ManifestFile::load(crate_root, crate_root)?;
+    let package = parent.document.get("package").and_then(Item::as_table_like);
+    let is_inherited = |item: &Item| {
+        item.as_table_like()
+            .and_then(|table| table.get("workspace"))
+            .and_then(Item::as_bool)
+            == Some(true)
+    };
+    let mut metadata = PackageMetadata {
+        edition: package
+            .and_then(|package| package.get("edition"))
+            .and_then(Item::as_str)
+            .map(str::to_owned),
+        ..Default::default()
+    };
+    let Some(workspace_root) = find_workspace_root(crate_root, &parent.document)? else {
+        return Ok(metadata);
+    };
+    let workspace_manifest = if normalize(&workspace_root) == normalize(crate_root) {
+        None
+    } else {
+        Some(ManifestFile::load(crate_root, &workspace_root)?.document)
+    };
+    let workspace = workspace_manifest
+        .as_ref()
+        .unwrap_or(&parent.document)
+        .get("workspace");
+    if relative_path(&workspace_root, target_crate_root).starts_with("..") {
+        // The new crate can't inherit from a workspace it isn't part of.
+        if metadata.edition.is_none() {
+            metadata.edition = workspace
+                .and_then(|workspace| workspace.get("package"))
+                .and_then(|package| package.get("edition"))
+                .and_then(Item::as_str)
+                .map(str::to_owned);
+        }
+        return Ok(metadata);
+    }
+    metadata.workspace_fields = package
+        .into_iter()
+        .flat_map(|package| package.iter())
+        .filter(|(_, item)| is_inherited(item))
+        .map(|(key, _)| key.to_owned())
+        .collect();
+    metadata.workspace_lints = workspace.is_some_and(|workspace| workspace.get("lints").is_some());
+    Ok(metadata)
+}
+
+/// Content of the Cargo.toml of a new crate named [target_crate_name], with the given
+/// [metadata], and [dependencies] and features.
+pub fn target_crate_manifest(
+    target_crate_name: &str,
+    metadata: &PackageMetadata,
+    dependencies: &TargetDependencies,
+) -> Result<String, ManifestError> {
+    let inherited = || {
+        let mut field = InlineTable::new();
+        field.insert("workspace", true.into());
+        field.set_dotted(true);
+        value(field)
+    };
+    let mut package = Table::new();
+    package.insert("name", value(target_crate_name));
+    // Without an edition, the moved code was compiled as Cargo's default edition, 2015.
+    for (field, default) in [("version", "0.0.1"), ("edition", "2015")] {
+        if metadata
+            .workspace_fields
+            .iter()
+            .any(|inherited| inherited == field)
+        {
+            package.insert(field, inherited());
+        } else if field == "edition" {
+            package.insert(field, value(metadata.edition.as_deref().unwrap_or(default)));
+        } else {
+            package.insert(field, value(default));
+        }
+    }
+    for field in &metadata.workspace_fields {
+        if !package.contains_key(field) {
+            package.insert(field, inherited());
+        }
+    }
+    let mut manifest = DocumentMut::new();
+    manifest.insert("package", Item::Table(package));
+    let insert_dependencies = |table: &mut Table,
+                               dependencies: &DepsSet,
+                               dev_dependencies: &DepsSet|
+     -> Result<(), ManifestError> {
+        for (key, dependencies) in [
+            ("dependencies", dependencies),
+            ("dev-dependencies", dev_dependencies),
+        ] {
+            if dependencies.is_empty() {
+                continue;
+            }
+            let mut dependency_table = Table::new();
+            for (name, dependency) in dependencies {
+                let dependency = dependency_value(dependency).map_err(|error| {
+                    ManifestError::NotSerializable(target_crate_name.to_owned(), error.to_string())
+                })?;
+                dependency_table.insert(name, value(dependency));
+            }
+            table.insert(key, Item::Table(dependency_table));
+        }
+        Ok(())
+    };
+    insert_dependencies(
+        manifest.as_table_mut(),
+        &dependencies.dependencies,
+        &dependencies.dev_dependencies,
+    )?;
+    if !dependencies.target.is_empty() {
+        // `[target.'cfg(unix)'.dependencies]` rather than `[target]` and `[target.'cfg(unix)']`.
+        let mut targets = Table::new();
+        targets.set_implicit(true);
+        for (platform, target) in &dependencies.target {
+            let mut table = Table::new();
+            table.set_implicit(true);
+            insert_dependencies(&mut table, &target.dependencies, &target.dev_dependencies)?;
+            // Cargo's documentation quotes `cfg()` expressions with single quotes, rather than
+            // the double quotes of toml_edit.
+            let is_bare = platform
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            let key = Some(format!("'{platform}'"))
+                .filter(|_| !is_bare)
+                .and_then(|literal| toml_edit::Key::parse(&literal).ok())
+                .and_then(|keys| keys.into_iter().next())
+                .unwrap_or_else(|| toml_edit::Key::new(platform));
+            targets.insert_formatted(&key, Item::Table(table));
+        }
+        manifest.insert("target", Item::Table(targets));
+    }
+    if !dependencies.features.is_empty() {
+        let mut features = Table::new();
+        for (feature, entries) in &dependencies.features {
+            features.insert(feature, value(Array::from_iter(entries)));
+        }
+        manifest.insert("features", Item::Table(features));
+    }
+    if metadata.workspace_lints {
+        let mut lints = Table::new();
+        lints.insert("workspace", value(true));
+        manifest.insert("lints", Item::Table(lints));
+    }
+    Ok(manifest.to_string())
+}
+
+/// Resolves `.` and `..` components without touching the file system, since the path may not
+/// exist yet.
+fn normalize(path: &Path) -> PathBuf {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_owned());
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// The path to `to` from the directory `from`.
+pub(crate) fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from = normalize(from);
+    let to = normalize(to);
+    let common = from
+        .components()
+        .zip(to.components())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative = PathBuf::new();
+    for _ in from.components().skip(common) {
+        relative.push("..");
+    }
+    relative.extend(to.components().skip(common));
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    relative
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+    use std::fs::read_to_string;
+    use std::path::{Path, PathBuf};
+
+    use cargo_toml::{Dependency, DependencyDetail, InheritedDependencyDetail, Target};
+    use pretty_assertions::assert_eq;
+
+    use crate::dependencies::{ModuleName, list_modules};
+    use crate::edit::{SourceChange, TextEdit};
+    use crate::refactor::ModuleMapping;
+
+    use super::{
+        FeatureUsage, ForwardedFeatures, HoistedDependencies, ManifestError, PackageMetadata,
+        ParentDependency, TargetDependencies, VersionPinning, copy_dependencies, crate_references,
+        hoist_shared_dependencies, link_target_crate, package_metadata, pin_versions,
+        relative_path, target_crate_manifest, wire_features,
+    };
+
+    fn fixture() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/deps")
+    }
+
+    #[test]
+    fn finds_referenced_crates() {
+        let crate_modules = list_modules(&fixture()).unwrap();
+        let extracted = [ModuleName::from("crate::store")];
+        let mapping = ModuleMapping::new(&extracted, &extracted, &crate_modules.modules).unwrap();
+        assert_eq!(
+            crate_references(&fixture(), &crate_modules, &mapping)
+                .unwrap()
+                .extracted,
+            BTreeSet::from(
+                [
+                    "json",
+                    "libc",
+                    "local_store",
+                    "pretty_assertions",
+                    "prometheus",
+                    "random",
+                    "serde",
+                    "serde_json",
+                    "tempfile",
+                    "tokio",
+                ]
+                .map(str::to_owned)
+            )
+        );
+        assert_eq!(
+            crate_references(&fixture(), &crate_modules, &mapping)
+                .unwrap()
+                .tests,
+            BTreeSet::from(["pretty_assertions".to_owned(), "tempfile".to_owned()])
+        );
+    }
+
+    #[test]
+    fn copies_used_dependencies() {
+        let names = [
+            "libc",
+            "local_store",
+            "pretty_assertions",
+            "random",
+            "serde",
+            "tempfile",
+            "tokio",
+        ]
+        .map(str::to_owned)
+        .into();
+        let test_names = ["pretty_assertions", "tempfile"].map(str::to_owned).into();
+        let dependencies = copy_dependencies(
+            &fixture(),
+            &fixture().join("crates/store"),
+            &names,
+            &test_names,
+            &BTreeMap::new(),
+        )
+        .unwrap();
+        assert_eq!(
+            dependencies.dependencies.keys().collect::<Vec<_>>(),
+            vec!["local-store", "random", "serde", "tokio"]
+        );
+        assert_eq!(
+            dependencies.dependencies["local-store"]
+                .detail()
+                .unwrap()
+                .path
+                .as_deref(),
+            Some("../../../local-store")
+        );
+        assert_eq!(
+            dependencies.dependencies["random"].detail().unwrap(),
+            &DependencyDetail {
+                version: Some("2".to_owned()),
+                package: Some("fastrand".to_owned()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            dependencies.dependencies["tokio"].detail().unwrap(),
+            &DependencyDetail {
+                version: Some("1".to_owned()),
+                features: vec!["rt".to_owned()],
+                optional: true,
+                default_features: false,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            dependencies.dev_dependencies,
+            [
+                (
+                    "pretty_assertions".to_owned(),
+                    Dependency::Simple("1".to_owned())
+                ),
+                ("tempfile".to_owned(), Dependency::Simple("3".to_owned())),
+            ]
+            .into()
+        );
+        assert_eq!(
+            dependencies.target,
+            [(
+                "cfg(unix)".to_owned(),
+                Target {
+                    dependencies: [("libc".to_owned(), Dependency::Simple("0.2".to_owned()))]
+                        .into(),
+                    ..Default::default()
+                }
+            )]
+            .into()
+        );
+    }
+
+    #[test]
+    fn renames_dependencies_referred_to_by_crate_aliases() {
+        let crate_modules = list_modules(&fixture()).unwrap();
+        assert_eq!(
+            crate_modules.crate_aliases,
+            [("json".to_owned(), "serde_json".to_owned())].into()
+        );
+        let names = ["json", "random"].map(str::to_owned).into();
+        let dependencies = copy_dependencies(
+            &fixture(),
+            &fixture().join("crates/store"),
+            &names,
+            &BTreeSet::new(),
+            &crate_modules.crate_aliases,
+        )
+        .unwrap();
+        assert_eq!(
+            dependencies.dependencies,
+            [
+                (
+                    "json".to_owned(),
+                    Dependency::Detailed(Box::new(DependencyDetail {
+                        version: Some("1".to_owned()),
+                        package: Some("serde_json".to_owned()),
+                        ..Default::default()
+                    }))
+                ),
+                (
+                    "random".to_owned(),
+                    Dependency::Detailed(Box::new(DependencyDetail {
+                        version: Some("2".to_owned()),
+                        package: Some("fastrand".to_owned()),
+                        ..Default::default()
+                    }))
+                ),
+            ]
+            .into()
+        );
+    }
+
+    #[test]
+    fn wires_features() {
+        let crate_root = fixture();
+        let crate_modules = list_modules(&crate_root).unwrap();
+        let extracted = [ModuleName::from("crate::store")];
+        let mapping = ModuleMapping::new(&extracted, &extracted, &crate_modules.modules).unwrap();
+        let usage = crate_references(&crate_root, &crate_modules, &mapping)
+            .unwrap()
+            .features;
+        assert_eq!(
+            usage,
+            FeatureUsage {
+                features: BTreeSet::from(["metrics".to_owned(), "tokio".to_owned()]),
+                gated_crates: [
+                    (
+                        "prometheus".to_owned(),
+                        BTreeSet::from(["metrics".to_owned()])
+                    ),
+                    ("tokio".to_owned(), BTreeSet::from(["tokio".to_owned()])),
+                ]
+                .into(),
+                ..Default::default()
+            }
+        );
+
+        let target_crate_root = crate_root.join("crates/store");
+        let mut dependencies = copy_dependencies(
+            &crate_root,
+            &target_crate_root,
+            &crate_references(&crate_root, &crate_modules, &mapping)
+                .unwrap()
+                .extracted,
+            &BTreeSet::new(),
+            &BTreeMap::new(),
+        )
+        .unwrap();
+        let forwarded = wire_features(&crate_root, "store", &mut dependencies, &usage).unwrap();
+        assert_eq!(
+            dependencies.features,
+            [
+                (
+                    "metrics".to_owned(),
+                    vec!["dep:prometheus".to_owned(), "tokio?/time".to_owned()]
+                ),
+                ("tokio".to_owned(), vec!["dep:tokio".to_owned()]),
+            ]
+            .into()
+        );
+        assert_eq!(
+            forwarded,
+            ForwardedFeatures {
+                features: [
+                    ("metrics".to_owned(), vec!["store/metrics".to_owned()]),
+                    (
+                        "tokio".to_owned(),
+                        vec!["dep:tokio".to_owned(), "store/tokio".to_owned()]
+                    ),
+                ]
+                .into(),
+                ..Default::default()
+            }
+        );
+
+        let changes = link_target_crate(
+            &crate_root,
+            "store",
+            &target_crate_root,
+            &HoistedDependencies::default(),
+            &forwarded,
+        )
+        .unwrap();
+        assert!(
+            apply_changes(&crate_root, &changes.changes)[0]
+                .1
+                .contains(concat!(
+                    "[features]\n",
+                    "metrics = [\"dep:prometheus\", \"tokio?/time\", \"store/metrics\"]\n",
+                    "tokio = [\"dep:tokio\", \"store/tokio\"]\n",
+                ))
+        );
+    }
+
+    #[test]
+    fn refuses_to_replace_existing_dependency() {
+        let crate_root = fixture();
+        for (name, existing) in [
+            ("local_store", "local-store"),
+            ("pretty-assertions", "pretty_assertions"),
+        ] {
+            assert_eq!(
+                link_target_crate(
+                    &crate_root,
+                    name,
+                    &crate_root.join("crates/store"),
+                    &HoistedDependencies::default(),
+                    &ForwardedFeatures::default(),
+                ),
+                Err(ManifestError::DependencyExists(
+                    crate_root.join("Cargo.toml"),
+                    existing.to_owned()
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn makes_modules_declared_with_a_feature_optional() {
+        let crate_root = fixture();
+        let crate_modules = list_modules(&crate_root).unwrap();
+        let extracted = [ModuleName::from("crate::metrics")];
+        let mapping = ModuleMapping::new(&extracted, &extracted, &crate_modules.modules).unwrap();
+        let usage = crate_references(&crate_root, &crate_modules, &mapping)
+            .unwrap()
+            .features;
+        assert_eq!(
+            usage,
+            FeatureUsage {
+                features: BTreeSet::from(["metrics".to_owned()]),
+                gated_crates: [(
+                    "prometheus".to_owned(),
+                    BTreeSet::from(["metrics".to_owned()])
+                )]
+                .into(),
+                declaration_features: Some(BTreeSet::from(["metrics".to_owned()])),
+                declared_for_tests: false,
+            }
+        );
+
+        let target_crate_root = crate_root.join("crates/metrics");
+        let mut dependencies = copy_dependencies(
+            &crate_root,
+            &target_crate_root,
+            &crate_references(&crate_root, &crate_modules, &mapping)
+                .unwrap()
+                .extracted,
+            &BTreeSet::new(),
+            &BTreeMap::new(),
+        )
+        .unwrap();
+        let forwarded =
+            wire_features(&crate_root, "deps-metrics", &mut dependencies, &usage).unwrap();
+        assert_eq!(
+            dependencies.features,
+            [("metrics".to_owned(), vec!["dep:prometheus".to_owned()])].into()
+        );
+        assert_eq!(
+            forwarded,
+            ForwardedFeatures {
+                features: [(
+                    "metrics".to_owned(),
+                    vec![
+                        "deps-metrics?/metrics".to_owned(),
+                        "dep:deps-metrics".to_owned()
+                    ]
+                )]
+                .into(),
+                dependency: ParentDependency::Optional,
+                warnings: Vec::new(),
+            }
+        );
+
+        let changes = link_target_crate(
+            &crate_root,
+            "deps-metrics",
+            &target_crate_root,
+            &HoistedDependencies::default(),
+            &forwarded,
+        )
+        .unwrap();
+        let parent = &apply_changes(&crate_root, &changes.changes)[0].1;
+        assert!(parent.contains(
+            "deps-metrics = { path = \"crates/metrics\", optional = true }\n\n[features]\n"
+        ));
+        assert!(parent.contains(concat!(
+            "metrics = [\"dep:prometheus\", \"tokio?/time\", \"deps-metrics?/metrics\", ",
+            "\"dep:deps-metrics\"]\n"
+        )));
+    }
+
+    #[test]
+    fn forwards_features_enabling_optional_dependencies() {
+        let crate_root = fixture();
+        let mut dependencies = copy_dependencies(
+            &crate_root,
+            &crate_root.join("crates/store"),
+            &BTreeSet::from(["prometheus".to_owned(), "tokio".to_owned()]),
+            &BTreeSet::new(),
+            &BTreeMap::new(),
+        )
+        .unwrap();
+        let forwarded = wire_features(
+            &crate_root,
+            "store",
+            &mut dependencies,
+            &FeatureUsage {
+                features: BTreeSet::from(["json".to_owned()]),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(dependencies.features, [("json".to_owned(), vec![])].into());
+        assert_eq!(
+            forwarded.features,
+            [
+                // `tokio?/time` doesn't enable tokio.
+                ("metrics".to_owned(), vec!["store/prometheus".to_owned()]),
+                (
+                    "tokio".to_owned(),
+                    vec!["dep:tokio".to_owned(), "store/tokio".to_owned()]
+                ),
+            ]
+            .into()
+        );
+        assert_eq!(forwarded.warnings.len(), 1);
+    }
+
+    fn workspace_fixture() -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/workspace")
+    }
+
+    /// The content of the files after the changes.
+    fn apply_changes(crate_root: &Path, changes: &[SourceChange]) -> Vec<(PathBuf, String)> {
+        changes
+            .iter()
+            .map(|change| {
+                let content = read_to_string(crate_root.join(&change.file)).unwrap();
+                (change.file.clone(), change.apply(&content).unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn adds_target_crate_to_workspace() {
+        let crate_root = workspace_fixture().join("app");
+        let changes = link_target_crate(
+            &crate_root,
+            "app-auth",
+            &workspace_fixture().join("app-auth"),
+            &HoistedDependencies::default(),
+            &ForwardedFeatures::default(),
+        )
+        .unwrap();
+        assert_eq!(changes.warnings, Vec::<String>::new());
+        assert_eq!(
+            apply_changes(&crate_root, &changes.changes),
+            vec![
+                (
+                    PathBuf::from("Cargo.toml"),
+                    concat!(
+                        "[package]\n",
+                        "name = \"app\"\n",
+                        "version = \"0.1.0\"\n",
+                        "edition.workspace = true\n",
+                        "license.workspace = true\n",
+                        "\n",
+                        "[dependencies]\n",
+                        "log = \"0.4\"\n",
+                        "# Validation of the inputs.\n",
+                        "regex = \"1\"\n",
+                        "serde.workspace = true\n",
+                        "uuid = { version = \"1\", features = [\"v4\"] }\n",
+                        "app-auth = { path = \"../app-auth\" }\n",
+                        "\n",
+                        "[lints]\n",
+                        "workspace = true\n",
+                    )
+                    .to_owned()
+                ),
+                (
+                    PathBuf::from("../Cargo.toml"),
+                    concat!(
+                        "[workspace]\n",
+                        "# Applications first, then libraries.\n",
+                        "members = [\n",
+                        "    \"app\",\n",
+                        "    \"libs/*\",\n",
+                        "    \"app-auth\",\n",
+                        "]\n",
+                        "default-members = [\"app\", \"app-auth\"]\n",
+                        "resolver = \"2\"\n",
+                        "\n",
+                        "[workspace.package]\n",
+                        "edition = \"2024\"\n",
+                        "license = \"MIT\"\n",
+                        "\n",
+                        "[workspace.dependencies]\n",
+                        "serde = { version = \"1\", features = [\"derive\"] }\n",
+                        "uuid = \"1\"\n",
+                        "\n",
+                        "[workspace.lints.rust]\n",
+                        "unsafe_code = \"forbid\"\n",
+                    )
+                    .to_owned()
+                ),
+            ]
+        );
+        assert_eq!(
+            changes.changes[0].edits,
+            vec![TextEdit::insert(
+                226,
+                "app-auth = { path = \"../app-auth\" }\n"
+            )]
+        );
+    }
+
+    #[test]
+    fn keeps_workspace_members_covered_by_a_glob() {
+        let crate_root = workspace_fixture().join("app");
+        let changes = link_target_crate(
+            &crate_root,
+            "auth",
+            &workspace_fixture().join("libs/auth"),
+            &HoistedDependencies::default(),
+            &ForwardedFeatures::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            apply_changes(&crate_root, &changes.changes)[1].1,
+            concat!(
+                "[workspace]\n",
+                "# Applications first, then libraries.\n",
+                "members = [\n",
+                "    \"app\",\n",
+                "    \"libs/*\",\n",
+                "]\n",
+                "default-members = [\"app\", \"libs/auth\"]\n",
+                "resolver = \"2\"\n",
+                "\n",
+                "[workspace.package]\n",
+                "edition = \"2024\"\n",
+                "license = \"MIT\"\n",
+                "\n",
+                "[workspace.dependencies]\n",
+                "serde = { version = \"1\", features = [\"derive\"] }\n",
+                "uuid = \"1\"\n",
+                "\n",
+                "[workspace.lints.rust]\n",
+                "unsafe_code = \"forbid\"\n",
+            )
+        );
+
+        let changes = link_target_crate(
+            &crate_root,
+            "auth",
+            &workspace_fixture().join("../auth"),
+            &HoistedDependencies::default(),
+            &ForwardedFeatures::default(),
+        )
+        .unwrap();
+        assert_eq!(changes.changes.len(), 1);
+        assert_eq!(changes.warnings.len(), 1);
+    }
+
+    #[test]
+    fn hoists_shared_dependencies() {
+        let crate_root = workspace_fixture().join("app");
+        let target_crate_root = workspace_fixture().join("app-auth");
+        let crate_modules = list_modules(&crate_root).unwrap();
+        let extracted = [ModuleName::from("crate::auth")];
+        let mapping = ModuleMapping::new(&extracted, &extracted, &crate_modules.modules).unwrap();
+        let mut dependencies = copy_dependencies(
+            &crate_root,
+            &target_crate_root,
+            &crate_references(&crate_root, &crate_modules, &mapping)
+                .unwrap()
+                .extracted,
+            &BTreeSet::new(),
+            &BTreeMap::new(),
+        )
+        .unwrap();
+        let hoisted = hoist_shared_dependencies(
+            &crate_root,
+            &target_crate_root,
+            &mut dependencies,
+            &crate_references(&crate_root, &crate_modules, &mapping)
+                .unwrap()
+                .parent,
+        )
+        .unwrap();
+        let inherited = Dependency::Inherited(InheritedDependencyDetail {
+            workspace: true,
+            ..Default::default()
+        });
+        assert_eq!(
+            hoisted.workspace_dependencies,
+            [("regex".to_owned(), Dependency::Simple("1".to_owned()))].into()
+        );
+        assert_eq!(hoisted.inherited, BTreeSet::from(["regex".to_owned()]));
+        assert_eq!(hoisted.warnings.len(), 1);
+        assert!(hoisted.warnings[0].starts_with("not hoisting uuid"));
+        assert_eq!(
+            dependencies.dependencies["log"],
+            Dependency::Simple("0.4".to_owned())
+        );
+        assert_eq!(dependencies.dependencies["regex"], inherited);
+        assert_eq!(dependencies.dependencies["serde"], inherited);
+        assert!(matches!(
+            dependencies.dependencies["uuid"],
+            Dependency::Detailed(_)
+        ));
+
+        let changes = link_target_crate(
+            &crate_root,
+            "app-auth",
+            &target_crate_root,
+            &hoisted,
+            &ForwardedFeatures::default(),
+        )
+        .unwrap();
+        let diffs = changes
+            .changes
+            .iter()
+            .map(|change| {
+                change
+                    .diff(&read_to_string(crate_root.join(&change.file)).unwrap())
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        assert!(diffs[0].contains(concat!(
+            " # Validation of the inputs.\n",
+            "-regex = \"1\"\n",
+            "+regex.workspace = true\n"
+        )));
+        assert!(diffs[1].contains(" uuid = \"1\"\n+regex = \"1\"\n"));
+    }
+
+    #[test]
+    fn pins_versions_from_lockfile() {
+        let crate_root = workspace_fixture().join("app");
+        let crate_modules = list_modules(&crate_root).unwrap();
+        let extracted = [ModuleName::from("crate::auth")];
+        let mapping = ModuleMapping::new(&extracted, &extracted, &crate_modules.modules).unwrap();
+        let copy = |target_crate_root: &Path| {
+            copy_dependencies(
+                &crate_root,
+                target_crate_root,
+                &crate_references(&crate_root, &crate_modules, &mapping)
+                    .unwrap()
+                    .extracted,
+                &BTreeSet::new(),
+                &BTreeMap::new(),
+            )
+            .unwrap()
+        };
+
+        let target_crate_root = workspace_fixture().join("app-auth");
+        let mut dependencies = copy(&target_crate_root);
+        let copied = dependencies.clone();
+        let warnings =
+            pin_versions(&crate_root, &target_crate_root, &mut dependencies, None).unwrap();
+        assert_eq!(warnings, Vec::<String>::new());
+        assert_eq!(dependencies, copied);
+
+        let outside = workspace_fixture().join("../app-auth");
+        let warnings = pin_versions(&crate_root, &outside, &mut copy(&outside), None).unwrap();
+        assert_eq!(warnings.len(), 3);
+        assert!(warnings[0].starts_with("log = \"0.4\" is locked to 0.4.22 in "));
+
+        pin_versions(
+            &crate_root,
+            &target_crate_root,
+            &mut dependencies,
+            Some(VersionPinning::Exact),
+        )
+        .unwrap();
+        assert_eq!(
+            dependencies.dependencies["log"],
+            Dependency::Simple("=0.4.22".to_owned())
+        );
+        assert_eq!(
+            dependencies.dependencies["uuid"].detail().unwrap(),
+            &DependencyDetail {
+                version: Some("=1.10.0".to_owned()),
+                features: vec!["v4".to_owned()],
+                ..Default::default()
+            }
+        );
+        // Inherited from the workspace.
+        assert_eq!(
+            dependencies.dependencies["serde"],
+            copied.dependencies["serde"]
+        );
+
+        let mut dependencies = copy(&target_crate_root);
+        dependencies
+            .dependencies
+            .insert("regex".to_owned(), Dependency::Simple("2".to_owned()));
+        let warnings = pin_versions(
+            &crate_root,
+            &target_crate_root,
+            &mut dependencies,
+            Some(VersionPinning::Minimal),
+        )
+        .unwrap();
+        assert_eq!(
+            dependencies.dependencies["log"],
+            Dependency::Simple("0.4.22".to_owned())
+        );
+        assert_eq!(
+            dependencies.dependencies["regex"],
+            Dependency::Simple("2".to_owned())
+        );
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].starts_with("regex = \"2\" matches no version locked in "));
+    }
+
+    #[test]
+    fn inherits_package_metadata_from_workspace() {
+        let crate_root = workspace_fixture().join("app");
+        assert_eq!(
+            package_metadata(&crate_root, &workspace_fixture().join("app-auth")).unwrap(),
+            PackageMetadata {
+                workspace_fields: vec!["edition".to_owned(), "license".to_owned()],
+                edition: None,
+                workspace_lints: true,
+            }
+        );
+        assert_eq!(
+            package_metadata(&crate_root, &workspace_fixture().join("../app-auth")).unwrap(),
+            PackageMetadata {
+                edition: Some("2024".to_owned()),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn computes_relative_paths() {
+        assert_eq!(
+            relative_path(
+                Path::new("/work/app/../app-auth"),
+                Path::new("/work/app/local")
+            ),
+            PathBuf::from("../app/local")
+        );
+        assert_eq!(
+            relative_path(Path::new("/work"), Path::new("/work")),
+            PathBuf::from(".")
+        );
+    }
+
+    #[test]
+    fn writes_dependencies_as_inline_tables() {
+        let dependencies = TargetDependencies {
+            dependencies: [
+                ("serde_json".to_owned(), Dependency::Simple("1".to_owned())),
+                (
+                    "serde".to_owned(),
+                    Dependency::Detailed(Box::new(DependencyDetail {
+                        version: Some("1".to_owned()),
+                        features: vec!["derive".to_owned()],
+                        ..Default::default()
+                    })),
+                ),
+            ]
+            .into(),
+            dev_dependencies: [(
+                "pretty_assertions".to_owned(),
+                Dependency::Simple("1".to_owned()),
+            )]
+            .into(),
+            target: [(
+                "cfg(unix)".to_owned(),
+                Target {
+                    dependencies: [("libc".to_owned(), Dependency::Simple("0.2".to_owned()))]
+                        .into(),
+                    ..Default::default()
+                },
+            )]
+            .into(),
+            features: [(
+                "json".to_owned(),
+                vec!["dep:serde_json".to_owned(), "serde/std".to_owned()],
+            )]
+            .into(),
+        };
+        assert_eq!(
+            target_crate_manifest("auth", &PackageMetadata::default(), &dependencies).unwrap(),
+            concat!(
+                "[package]\n",
+                "name = \"auth\"\n",
+                "version = \"0.0.1\"\n",
+                "edition = \"2015\"\n",
+                "\n",
+                "[dependencies]\n",
+                "serde = { version = \"1\", features = [\"derive\"] }\n",
+                "serde_json = \"1\"\n",
+                "\n",
+                "[dev-dependencies]\n",
+                "pretty_assertions = \"1\"\n",
+                "\n",
+                "[target.'cfg(unix)'.dependencies]\n",
+                "libc = \"0.2\"\n",
+                "\n",
+                "[features]\n",
+                "json = [\"dep:serde_json\", \"serde/std\"]\n",
+            )
+        );
+    }
+
+    #[test]
+    fn inherits_metadata_from_workspace() {
+        let metadata = PackageMetadata {
+            workspace_fields: vec![
+                "edition".to_owned(),
+                "authors".to_owned(),
+                "version".to_owned(),
+            ],
+            edition: None,
+            workspace_lints: true,
+        };
+        let dependencies = TargetDependencies {
+            dependencies: [(
+                "tracing".to_owned(),
+                Dependency::Inherited(InheritedDependencyDetail {
+                    workspace: true,
+                    ..Default::default()
+                }),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            target_crate_manifest("auth", &metadata, &dependencies).unwrap(),
+            concat!(
+                "[package]\n",
+                "name = \"auth\"\n",
+                "version.workspace = true\n",
+                "edition.workspace = true\n",
+                "authors.workspace = true\n",
+                "\n",
+                "[dependencies]\n",
+                "tracing.workspace = true\n",
+                "\n",
+                "[lints]\n",
+                "workspace = true\n",
+            )
+        );
+    }
+}
@@ -0,0 +1,267 @@
+use std::cmp::Reverse;
+use std::fmt::Write;
+
+use serde::Serialize;
+use thiserror::Error;
+
+use crate::dependencies::{ItemCounts, ModuleName};
+use crate::graph::ModuleGraph;
+
+/// Coupling and size metrics of a single module.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ModuleMetrics {
+    pub module: ModuleName,
+    /// Number of modules of the crate that depend on this module.
+    pub fan_in: usize,
+    /// Number of modules of the crate this module depends on.
+    pub fan_out: usize,
+    /// `fan_out / (fan_in + fan_out)`: 0 for a module that nothing can break, 1 for a module
+    /// that nothing depends on.
+    pub instability: f64,
+    /// Number of distinct paths imported from the crate itself.
+    pub internal_dependencies: usize,
+    /// Number of distinct paths imported from other crates.
+    pub external_dependencies: usize,
+    pub lines: usize,
+    #[serde(flatten)]
+    pub items: ItemCounts,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricsSortKey {
+    Module,
+    FanIn,
+    FanOut,
+    Instability,
+    InternalDependencies,
+    ExternalDependencies,
+    Lines,
+    Items,
+}
+
+#[derive(Debug, Error)]
+pub enum MetricsError {
+    #[error("failed to serialize metrics: {0}")]
+    Serialization(serde_json::Error),
+}
+
+/// Computes the metrics of every module of the crate, sorted by module name.
+pub fn compute_metrics(graph: &ModuleGraph) -> Vec<ModuleMetrics> {
+    graph
+        .modules()
+        .iter()
+        .map(|(module, info)| {
+            let fan_in = graph.dependents(module).count();
+            let fan_out = graph.dependencies(module).count();
+            let (internal_dependencies, external_dependencies) = graph
+                .raw_dependencies(module)
+                .fold((0, 0), |(internal, external), dependency| {
+                    if dependency.is_crate_local() {
+                        (internal + 1, external)
+                    } else {
+                        (internal, external + 1)
+                    }
+                });
+            ModuleMetrics {
+                module: module.clone(),
+                fan_in,
+                fan_out,
+                instability: if fan_in + fan_out == 0 {
+                    0.0
+                } else {
+                    fan_out as f64 / (fan_in + fan_out) as f64
+                },
+                internal_dependencies,
+                external_dependencies,
+                lines: info.lines,
+                items: info.items,
+            }
+        })
+        .collect()
+}
+
+/// Sorts the metrics by `key`: alphabetically for module names, highest first otherwise.
+pub fn sort_metrics(metrics: &mut [ModuleMetrics], key: MetricsSortKey) {
+    match key {
+        MetricsSortKey::Module => metrics.sort_by(|a, b| a.module.cmp(&b.module)),
+        MetricsSortKey::FanIn => metrics.sort_by_key(|m| Reverse(m.fan_in)),
+        MetricsSortKey::FanOut => metrics.sort_by_key(|m| Reverse(m.fan_out)),
+        MetricsSortKey::Instability => {
+            metrics.sort_by(|a, b| b.instability.total_cmp(&a.instability))
+        }
+        MetricsSortKey::InternalDependencies => {
+            metrics.sort_by_key(|m| Reverse(m.internal_dependencies))
+        }
+        MetricsSortKey::ExternalDependencies => {
+            metrics.sort_by_key(|m| Reverse(m.external_dependencies))
+        }
+        MetricsSortKey::Lines => metrics.sort_by_key(|m| Reverse(m.lines)),
+        MetricsSortKey::Items => metrics.sort_by_key(|m| Reverse(m.items.total())),
+    }
+}
+
+const COLUMNS: [&str; 13] = [
+    "module",
+    "fan_in",
+    "fan_out",
+    "instability",
+    "internal_dependencies",
+    "external_dependencies",
+    "lines",
+    "functions",
+    "types",
+    "traits",
+    "impls",
+    "constants",
+    "macros",
+];
+
+fn row(metrics: &ModuleMetrics) -> [String; 13] {
+    [
+        metrics.module.to_string(),
+        metrics.fan_in.to_string(),
+        metrics.fan_out.to_string(),
+        format!("{:.2}", metrics.instability),
+        metrics.internal_dependencies.to_string(),
+        metrics.external_dependencies.to_string(),
+        metrics.lines.to_string(),
+        metrics.items.functions.to_string(),
+        metrics.items.types.to_string(),
+        metrics.items.traits.to_string(),
+        metrics.items.impls.to_string(),
+        metrics.items.constants.to_string(),
+        metrics.items.macros.to_string(),
+    ]
+}
+
+/// Formats the metrics as CSV, with a header line.
+pub fn to_csv(metrics: &[ModuleMetrics]) -> String {
+    // Module paths never contain commas or quotes, so no escaping is needed.
+    let mut csv = COLUMNS.join(",");
+    csv.push('\n');
+    for metrics in metrics {
+        csv.push_str(&row(metrics).join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Formats the metrics as a JSON array.
+pub fn to_json(metrics: &[ModuleMetrics]) -> Result<String, MetricsError> {
+    serde_json::to_string_pretty(metrics).map_err(MetricsError::Serialization)
+}
+
+/// Formats the metrics as a human-readable table.
+pub fn to_table(metrics: &[ModuleMetrics]) -> String {
+    let rows = metrics.iter().map(row).collect::<Vec<_>>();
+    let widths = rows.iter().fold(COLUMNS.map(str::len), |mut widths, row| {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+        widths
+    });
+    let mut table = String::new();
+    for row in std::iter::once(COLUMNS.map(str::to_owned)).chain(rows) {
+        let mut cells = row.iter().zip(widths);
+        if let Some((module, width)) = cells.next() {
+            write!(table, "{module:<width$}").unwrap();
+        }
+        for (cell, width) in cells {
+            write!(table, "  {cell:>width$}").unwrap();
+        }
+        table.push('\n');
+    }
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::dependencies::{ItemCounts, ModuleName, list_modules};
+    use crate::graph::ModuleGraph;
+
+    use super::{MetricsSortKey, ModuleMetrics, compute_metrics, sort_metrics, to_csv};
+
+    fn layered_metrics() -> Vec<ModuleMetrics> {
+        let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/layered");
+        compute_metrics(&ModuleGraph::new(&list_modules(&crate_root).unwrap()))
+    }
+
+    #[test]
+    fn computes_coupling() {
+        let metrics = layered_metrics();
+        let db = metrics
+            .iter()
+            .find(|m| m.module == "crate::db".into())
+            .unwrap();
+        assert_eq!(
+            db,
+            &ModuleMetrics {
+                module: "crate::db".into(),
+                fan_in: 2,
+                fan_out: 1,
+                instability: 1.0 / 3.0,
+                internal_dependencies: 1,
+                external_dependencies: 0,
+                lines: 11,
+                items: ItemCounts {
+                    types: 1,
+                    impls: 1,
+                    ..Default::default()
+                },
+            }
+        );
+        let api = metrics
+            .iter()
+            .find(|m| m.module == "crate::api".into())
+            .unwrap();
+        assert_eq!((api.fan_in, api.fan_out), (2, 2));
+        assert_eq!(
+            (api.internal_dependencies, api.external_dependencies),
+            (2, 1)
+        );
+    }
+
+    #[test]
+    fn counts_inline_modules_separately() {
+        let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/inline");
+        let metrics = compute_metrics(&ModuleGraph::new(&list_modules(&crate_root).unwrap()));
+        let lines = metrics
+            .iter()
+            .map(|m| (m.module.to_string(), m.lines))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            lines,
+            vec![
+                ("crate".to_owned(), 2),
+                ("crate::module_a".to_owned(), 3),
+                ("crate::module_a::module_b".to_owned(), 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn sorts_by_fan_in() {
+        let mut metrics = layered_metrics();
+        sort_metrics(&mut metrics, MetricsSortKey::FanIn);
+        assert_eq!(metrics[0].module, ModuleName::from("crate::api"));
+        assert_eq!(
+            metrics.last().unwrap().module,
+            ModuleName::from("crate::api::handlers")
+        );
+    }
+
+    #[test]
+    fn exports_csv() {
+        let metrics = layered_metrics();
+        let csv = to_csv(&metrics[..1]);
+        assert_eq!(
+            csv,
+            "module,fan_in,fan_out,instability,internal_dependencies,external_dependencies,lines,functions,types,traits,impls,constants,macros\n\
+             crate,0,1,1.00,1,0,7,1,0,0,0,0,0\n"
+        );
+    }
+}
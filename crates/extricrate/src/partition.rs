@@ -0,0 +1,564 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use crate::dependencies::ModuleName;
+use crate::graph::ModuleGraph;
+
+/// How many crates to split the crate into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PartitionTarget {
+    /// At most this many new crates.
+    Crates(usize),
+    /// New crates of at most this many lines, unless a dependency cycle forces a bigger one.
+    MaxLines(usize),
+}
+
+/// A new crate to create, containing whole module subtrees.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlannedExtraction {
+    pub crate_name: String,
+    /// Roots of the subtrees to extract together.
+    pub modules: Vec<ModuleName>,
+    pub lines: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PartitionPlan {
+    /// Extractions to apply in order: a crate only depends on the crates extracted before it.
+    pub extractions: Vec<PlannedExtraction>,
+    /// Subtrees that depend on the parent crate, and thus have to stay in it.
+    pub remaining: Vec<ModuleName>,
+    /// Number of dependencies between modules that end up in different crates.
+    pub cross_crate_dependencies: usize,
+}
+
+/// A set of subtrees that end up in the same crate.
+#[derive(Debug)]
+struct Group {
+    units: BTreeSet<ModuleName>,
+    lines: usize,
+}
+
+/// Proposes a partition of the crate into acyclic crates.
+///
+/// The subtrees rooted at `depth` (1 for the children of the crate root) are the units of the
+/// partition: modules above them stay in the parent crate, and so do the units that depend on
+/// the parent crate. The other units are grouped along dependency cycles, then merged greedily
+/// along their heaviest dependencies until `target` is reached.
+pub fn plan_partition(
+    graph: &ModuleGraph,
+    crate_name: &str,
+    target: PartitionTarget,
+    depth: usize,
+) -> PartitionPlan {
+    let depth = depth.max(1);
+    let unit_of = |module: &ModuleName| {
+        let mut unit = module.clone();
+        while unit.depth() > depth {
+            unit = unit.parent()?;
+        }
+        (unit.depth() == depth).then_some(unit)
+    };
+
+    let mut unit_lines: BTreeMap<ModuleName, usize> = BTreeMap::new();
+    for (module, info) in graph.modules() {
+        if let Some(unit) = unit_of(module) {
+            *unit_lines.entry(unit).or_default() += info.lines;
+        }
+    }
+    // Dependencies between units, and from units to the parent crate.
+    let mut unit_edges: BTreeMap<(ModuleName, ModuleName), usize> = BTreeMap::new();
+    let mut pinned: BTreeSet<ModuleName> = BTreeSet::new();
+    for module in graph.modules().keys() {
+        let Some(from) = unit_of(module) else {
+            continue;
+        };
+        for dependency in graph.dependencies(module) {
+            match unit_of(dependency) {
+                Some(to) if to == from => {}
+                Some(to) => *unit_edges.entry((from.clone(), to)).or_default() += 1,
+                None => {
+                    pinned.insert(from.clone());
+                }
+            }
+        }
+    }
+    // Units depending on a unit that stays in the parent crate have to stay as well.
+    loop {
+        let newly_pinned = unit_edges
+            .keys()
+            .filter(|(from, to)| pinned.contains(to) && !pinned.contains(from))
+            .map(|(from, _)| from.clone())
+            .collect::<Vec<_>>();
+        if newly_pinned.is_empty() {
+            break;
+        }
+        pinned.extend(newly_pinned);
+    }
+
+    let units = unit_lines
+        .keys()
+        .filter(|unit| !pinned.contains(*unit))
+        .cloned()
+        .collect::<Vec<_>>();
+    let mut groups = strongly_connected_components(&units, &unit_edges)
+        .into_iter()
+        .map(|units| Group {
+            lines: units.iter().map(|unit| unit_lines[unit]).sum(),
+            units,
+        })
+        .collect::<Vec<_>>();
+
+    let mut weights = GroupWeights::new(&groups, &unit_edges);
+    while let Some((a, b)) = next_merge(&groups, &weights, target) {
+        let merged = groups.remove(b);
+        groups[a].units.extend(merged.units);
+        groups[a].lines += merged.lines;
+        weights.merge(a, b);
+    }
+
+    let order = topological_order(&groups, &weights);
+    let mut crate_names = HashSet::new();
+    let extractions = order
+        .into_iter()
+        .map(|index| {
+            let group = &groups[index];
+            let modules = group.units.iter().cloned().collect::<Vec<_>>();
+            PlannedExtraction {
+                crate_name: unique_crate_name(crate_name, &modules[0], &mut crate_names),
+                modules,
+                lines: group.lines,
+            }
+        })
+        .collect();
+
+    let crate_of = |module: &ModuleName| {
+        unit_of(module).and_then(|unit| groups.iter().position(|group| group.units.contains(&unit)))
+    };
+    let cross_crate_dependencies = graph
+        .modules()
+        .keys()
+        .map(|module| {
+            graph
+                .dependencies(module)
+                .filter(|dependency| crate_of(module) != crate_of(dependency))
+                .count()
+        })
+        .sum();
+
+    PartitionPlan {
+        extractions,
+        remaining: pinned.into_iter().collect(),
+        cross_crate_dependencies,
+    }
+}
+
+fn unique_crate_name(prefix: &str, module: &ModuleName, taken: &mut HashSet<String>) -> String {
+    let last_segment = module.as_str().rsplit("::").next().unwrap_or_default();
+    let base = format!("{prefix}_{last_segment}");
+    let mut name = base.clone();
+    let mut suffix = 2;
+    while !taken.insert(name.clone()) {
+        name = format!("{base}_{suffix}");
+        suffix += 1;
+    }
+    name
+}
+
+/// Weights of the dependencies between groups: `weights[a][b]` from group `a` to group `b`, and
+/// whether `a` depends on `b` directly or transitively: `reachable[a][b]`.
+///
+/// Kept up to date as groups are merged, so that the unit dependencies are only scanned once.
+#[derive(Debug)]
+struct GroupWeights {
+    weights: Vec<Vec<usize>>,
+    reachable: Vec<Vec<bool>>,
+}
+
+impl GroupWeights {
+    fn new(groups: &[Group], edges: &BTreeMap<(ModuleName, ModuleName), usize>) -> Self {
+        let group_of = groups
+            .iter()
+            .enumerate()
+            .flat_map(|(index, group)| group.units.iter().map(move |unit| (unit, index)))
+            .collect::<BTreeMap<_, _>>();
+        let mut weights = vec![vec![0; groups.len()]; groups.len()];
+        for ((from, to), weight) in edges {
+            if let (Some(&a), Some(&b)) = (group_of.get(from), group_of.get(to))
+                && a != b
+            {
+                weights[a][b] += weight;
+            }
+        }
+        let mut weights = Self {
+            weights,
+            reachable: Vec::new(),
+        };
+        weights.reachable = weights.reachability(&topological_order(groups, &weights));
+        weights
+    }
+
+    fn weight(&self, a: usize, b: usize) -> usize {
+        self.weights[a][b]
+    }
+
+    /// Groups that group `a` depends on.
+    fn dependencies(&self, a: usize) -> impl Iterator<Item = usize> + '_ {
+        (0..self.weights.len()).filter(move |&b| self.weights[a][b] > 0)
+    }
+
+    /// Merges group `b` into group `a`, with `a < b`, removing it like `Vec::remove` does.
+    ///
+    /// The merged group reaches what either group reached, and the groups that reached either
+    /// of them now reach all of that too.
+    fn merge(&mut self, a: usize, b: usize) {
+        debug_assert!(a < b);
+        let merged = self.weights.remove(b);
+        for (weight, merged) in self.weights[a].iter_mut().zip(merged) {
+            *weight += merged;
+        }
+        for row in &mut self.weights {
+            let merged = row.remove(b);
+            row[a] += merged;
+        }
+        self.weights[a][a] = 0;
+
+        let merged_reach = self.reachable[a]
+            .iter()
+            .zip(&self.reachable[b])
+            .map(|(from_a, from_b)| from_a | from_b)
+            .collect::<Vec<_>>();
+        for row in &mut self.reachable {
+            if row[a] || row[b] {
+                for (reached, &through) in row.iter_mut().zip(&merged_reach) {
+                    *reached |= through;
+                }
+                row[a] = true;
+            }
+        }
+        self.reachable[a] = merged_reach;
+        self.reachable.remove(b);
+        for row in &mut self.reachable {
+            let merged = row.remove(b);
+            row[a] |= merged;
+        }
+        self.reachable[a][a] = false;
+    }
+
+    /// For each group, the groups it depends on directly or transitively, given the groups in
+    /// topological `order`.
+    fn reachability(&self, order: &[usize]) -> Vec<Vec<bool>> {
+        let mut reachable = vec![vec![false; self.weights.len()]; self.weights.len()];
+        // Dependencies come first in the order, so their reachability is already known.
+        for &a in order {
+            let mut row = vec![false; self.weights.len()];
+            for b in self.dependencies(a) {
+                row[b] = true;
+                for (reached, &through) in row.iter_mut().zip(&reachable[b]) {
+                    *reached |= through;
+                }
+            }
+            reachable[a] = row;
+        }
+        reachable
+    }
+}
+
+/// Whether `to` can be reached from `from` through at least one other group.
+fn has_indirect_path(weights: &GroupWeights, from: usize, to: usize) -> bool {
+    weights
+        .dependencies(from)
+        .any(|next| next != to && weights.reachable[next][to])
+}
+
+/// Dependencies between two groups, then the opposite of their size: higher is better.
+type MergeScore = (usize, Reverse<usize>);
+
+/// Picks the next two groups to merge: the pair with the heaviest dependencies between them
+/// whose merge doesn't create a cycle and respects the target.
+fn next_merge(
+    groups: &[Group],
+    weights: &GroupWeights,
+    target: PartitionTarget,
+) -> Option<(usize, usize)> {
+    if let PartitionTarget::Crates(crates) = target
+        && groups.len() <= crates.max(1)
+    {
+        return None;
+    }
+    let mut best: Option<(MergeScore, (usize, usize))> = None;
+    for a in 0..groups.len() {
+        for b in a + 1..groups.len() {
+            let lines = groups[a].lines + groups[b].lines;
+            if let PartitionTarget::MaxLines(max_lines) = target
+                && lines > max_lines
+            {
+                continue;
+            }
+            let weight = weights.weight(a, b) + weights.weight(b, a);
+            if weight == 0 && matches!(target, PartitionTarget::MaxLines(_)) {
+                // Merging unrelated groups doesn't remove any cross-crate dependency.
+                continue;
+            }
+            if has_indirect_path(weights, a, b) || has_indirect_path(weights, b, a) {
+                continue;
+            }
+            let score = (weight, Reverse(lines));
+            if best
+                .as_ref()
+                .is_none_or(|(best_score, _)| score > *best_score)
+            {
+                best = Some((score, (a, b)));
+            }
+        }
+    }
+    best.map(|(_, pair)| pair)
+}
+
+/// Orders the groups so that each group comes after the groups it depends on.
+///
+/// The groups are acyclic by construction. Were they not, the groups left in a cycle would be
+/// placed anyway, smallest first.
+fn topological_order(groups: &[Group], weights: &GroupWeights) -> Vec<usize> {
+    let mut remaining_dependencies = (0..groups.len())
+        .map(|index| weights.dependencies(index).count())
+        .collect::<Vec<_>>();
+    let mut order = Vec::with_capacity(groups.len());
+    let mut placed = vec![false; groups.len()];
+    while order.len() < groups.len() {
+        let unplaced = (0..groups.len()).filter(|&index| !placed[index]);
+        let ready = unplaced
+            .clone()
+            .filter(|&index| remaining_dependencies[index] == 0)
+            .min_by_key(|&index| groups[index].units.first());
+        debug_assert!(ready.is_some(), "groups are acyclic");
+        let Some(next) = ready.or_else(|| unplaced.min_by_key(|&index| groups[index].lines)) else {
+            break;
+        };
+        placed[next] = true;
+        order.push(next);
+        for (dependent, remaining) in remaining_dependencies.iter_mut().enumerate() {
+            if weights.weight(dependent, next) > 0 {
+                *remaining -= 1;
+            }
+        }
+    }
+    order
+}
+
+/// Tarjan's algorithm, returning the components in a deterministic order.
+fn strongly_connected_components(
+    nodes: &[ModuleName],
+    edges: &BTreeMap<(ModuleName, ModuleName), usize>,
+) -> Vec<BTreeSet<ModuleName>> {
+    struct State<'a> {
+        edges: &'a BTreeMap<(ModuleName, ModuleName), usize>,
+        nodes: &'a [ModuleName],
+        index: BTreeMap<&'a ModuleName, usize>,
+        low_link: BTreeMap<&'a ModuleName, usize>,
+        stack: Vec<&'a ModuleName>,
+        on_stack: BTreeSet<&'a ModuleName>,
+        components: Vec<BTreeSet<ModuleName>>,
+    }
+
+    fn visit<'a>(state: &mut State<'a>, node: &'a ModuleName) {
+        let index = state.index.len();
+        state.index.insert(node, index);
+        state.low_link.insert(node, index);
+        state.stack.push(node);
+        state.on_stack.insert(node);
+        let successors = state
+            .nodes
+            .iter()
+            .filter(|to| state.edges.contains_key(&(node.clone(), (*to).clone())))
+            .collect::<Vec<_>>();
+        for successor in successors {
+            if !state.index.contains_key(successor) {
+                visit(state, successor);
+                let low_link = state.low_link[node].min(state.low_link[successor]);
+                state.low_link.insert(node, low_link);
+            } else if state.on_stack.contains(successor) {
+                let low_link = state.low_link[node].min(state.index[successor]);
+                state.low_link.insert(node, low_link);
+            }
+        }
+        if state.low_link[node] == state.index[node] {
+            let mut component = BTreeSet::new();
+            while let Some(member) = state.stack.pop() {
+                state.on_stack.remove(member);
+                component.insert(member.clone());
+                if member == node {
+                    break;
+                }
+            }
+            state.components.push(component);
+        }
+    }
+
+    let mut state = State {
+        edges,
+        nodes,
+        index: BTreeMap::new(),
+        low_link: BTreeMap::new(),
+        stack: Vec::new(),
+        on_stack: BTreeSet::new(),
+        components: Vec::new(),
+    };
+    for node in nodes {
+        if !state.index.contains_key(node) {
+            visit(&mut state, node);
+        }
+    }
+    let mut components = state.components;
+    components.sort();
+    components
+}
+
+/// Formats the plan as a human-readable list of extractions.
+pub fn to_text(plan: &PartitionPlan) -> String {
+    let mut text = String::new();
+    for (rank, extraction) in plan.extractions.iter().enumerate() {
+        writeln!(
+            text,
+            "{}. {} ({} lines): {}",
+            rank + 1,
+            extraction.crate_name,
+            extraction.lines,
+            itertools::join(&extraction.modules, ", ")
+        )
+        .unwrap();
+    }
+    if !plan.remaining.is_empty() {
+        writeln!(
+            text,
+            "staying in the parent crate: {}",
+            itertools::join(&plan.remaining, ", ")
+        )
+        .unwrap();
+    }
+    writeln!(
+        text,
+        "cross-crate dependencies: {}",
+        plan.cross_crate_dependencies
+    )
+    .unwrap();
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::dependencies::list_modules;
+    use crate::graph::ModuleGraph;
+
+    use super::{PartitionPlan, PartitionTarget, PlannedExtraction, plan_partition};
+
+    fn plan(fixture: &str, target: PartitionTarget) -> PartitionPlan {
+        let crate_root = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures")
+            .join(fixture);
+        let graph = ModuleGraph::new(&list_modules(&crate_root).unwrap());
+        plan_partition(&graph, fixture, target, 1)
+    }
+
+    #[test]
+    fn merges_along_heaviest_dependencies() {
+        assert_eq!(
+            plan("layered", PartitionTarget::Crates(2)),
+            PartitionPlan {
+                extractions: vec![
+                    PlannedExtraction {
+                        crate_name: "layered_util".to_owned(),
+                        modules: vec!["crate::util".into()],
+                        lines: 7,
+                    },
+                    PlannedExtraction {
+                        crate_name: "layered_api".to_owned(),
+                        modules: vec!["crate::api".into(), "crate::db".into()],
+                        lines: 24,
+                    },
+                ],
+                remaining: vec![],
+                cross_crate_dependencies: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn merges_without_creating_cycles() {
+        // `x` depends on `z` the most, but also through `y`.
+        let names = |plan: PartitionPlan| {
+            plan.extractions
+                .into_iter()
+                .map(|extraction| (extraction.crate_name, extraction.modules))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            names(plan("chain", PartitionTarget::Crates(2))),
+            vec![
+                (
+                    "chain_y".to_owned(),
+                    vec!["crate::y".into(), "crate::z".into()]
+                ),
+                ("chain_x".to_owned(), vec!["crate::x".into()]),
+            ]
+        );
+        assert_eq!(
+            names(plan("chain", PartitionTarget::Crates(1))),
+            vec![(
+                "chain_x".to_owned(),
+                vec!["crate::x".into(), "crate::y".into(), "crate::z".into()]
+            )]
+        );
+    }
+
+    #[test]
+    fn respects_max_lines() {
+        let plan = plan("layered", PartitionTarget::MaxLines(15));
+        assert_eq!(
+            plan.extractions
+                .iter()
+                .map(|extraction| extraction.crate_name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["layered_util", "layered_db", "layered_api"]
+        );
+    }
+
+    #[test]
+    fn keeps_cycles_in_one_crate() {
+        let plan = plan("cyclic", PartitionTarget::MaxLines(1));
+        assert_eq!(
+            plan.extractions
+                .iter()
+                .map(|extraction| extraction.modules.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                vec!["crate::a".into(), "crate::b".into()],
+                vec!["crate::c".into()]
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_modules_depending_on_the_parent() {
+        let plan = plan("pinned", PartitionTarget::Crates(5));
+        assert_eq!(
+            plan.remaining,
+            vec!["crate::server".into(), "crate::settings".into()]
+        );
+        assert_eq!(
+            plan.extractions,
+            vec![PlannedExtraction {
+                crate_name: "pinned_text".to_owned(),
+                modules: vec!["crate::text".into()],
+                lines: 3,
+            }]
+        );
+    }
+}
@@ -0,0 +1,1035 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt::Write;
+use std::fs::{self, read_to_string};
+use std::path::{Component, Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::dependencies::{
+    CrateModules, ModuleName, ModulePath, ModuleTree, check_self_contained, get_crate_name,
+    target_crate_lib_rs,
+};
+use crate::edit::{SourceChange, TextEdit, unified_diff};
+use crate::graph::ModuleGraph;
+use crate::manifest::{
+    HoistedDependencies, VersionPinning, copy_dependencies, crate_references,
+    hoist_shared_dependencies, link_target_crate, package_metadata, pin_versions,
+    target_crate_manifest, wire_features,
+};
+use crate::refactor::ModuleMapping;
+use crate::rewrite::{
+    check_declaration_paths, dropped_declaration_attributes, lift_inline_modules,
+    macro_path_warnings, remove_module_declarations, reroot_extracted_paths,
+    rewrite_parent_imports,
+};
+use crate::transaction::{Journal, JournalError, Transaction, hash_file, list_files};
+use crate::verify::{AppliedEdit, target_directory};
+
+#[derive(Debug, Error, PartialEq)]
+pub enum PlanError {
+    #[error("{0} changed since the extraction was planned")]
+    SourceChanged(PathBuf),
+    #[error("{0} is missing since the extraction was planned")]
+    SourceMissing(PathBuf),
+}
+
+/// A file of the new crate.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CreatedFile {
+    pub path: PathBuf,
+    pub content: String,
+}
+
+/// A file moved from the parent crate to the new crate.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MovedFile {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// Everything an extraction does, computed without touching the file system.
+///
+/// The plan can be saved to review or tweak it before applying it, as long as the source files
+/// don't change in between.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExtractionPlan {
+    /// Root of the parent crate. The source changes are relative to it.
+    pub crate_root: PathBuf,
+    pub target_crate_name: String,
+    pub target_crate_root: PathBuf,
+    pub created_files: Vec<CreatedFile>,
+    pub moved_files: Vec<MovedFile>,
+    /// Edits of the files of the parent crate, including the ones that are then moved.
+    pub source_changes: Vec<SourceChange>,
+    /// Things the reviewer should know about, that don't prevent the extraction.
+    pub warnings: Vec<String>,
+    /// SHA-256 of the files the plan edits or moves, when it was computed, relative to the
+    /// parent crate.
+    pub source_hashes: BTreeMap<PathBuf, String>,
+}
+
+/// How to extract modules, beyond which modules and where to.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ExtractionOptions {
+    /// Extract the modules of the crate that the modules depend on along with them.
+    pub with_dependencies: bool,
+    /// Move the dependencies both crates use to `[workspace.dependencies]`.
+    pub hoist_dependencies: bool,
+    /// Rewrite the version requirements of the new crate from the versions in Cargo.lock.
+    pub pinning: Option<VersionPinning>,
+}
+
+/// Plans the extraction of `modules` to a new crate, as `options` say.
+pub fn plan_extraction(
+    crate_root: &Path,
+    modules: &[ModulePath],
+    target_crate_name: &str,
+    target_crate_root: &Path,
+    crate_modules: &CrateModules,
+    options: &ExtractionOptions,
+) -> Result<ExtractionPlan, Box<dyn Error>> {
+    let requested = modules.iter().map(ModuleName::from).collect::<Vec<_>>();
+    let mut warnings = Vec::new();
+    let roots = if options.with_dependencies {
+        let roots = ModuleGraph::new(crate_modules).extraction_closure(&requested)?;
+        let added = roots
+            .iter()
+            .filter(|root| !requested.contains(root))
+            .collect::<Vec<_>>();
+        if !added.is_empty() {
+            warnings.push(format!(
+                "extracting {} along with their dependencies: {}",
+                itertools::join(&requested, ", "),
+                itertools::join(added, ", ")
+            ));
+        }
+        roots
+    } else {
+        check_self_contained(&requested, &crate_modules.use_statements)?;
+        requested.clone()
+    };
+    let mapping = ModuleMapping::new(&requested, &roots, &crate_modules.modules)?;
+    check_declaration_paths(crate_root, crate_modules, &mapping)?;
+
+    let source_dir = crate_root.join("src");
+    let target_source_dir = target_crate_root.join("src");
+    let moved_files = module_moves(
+        crate_root,
+        &crate_modules.modules,
+        &mapping,
+        &target_source_dir,
+    );
+
+    let mut source_changes = reroot_extracted_paths(crate_root, crate_modules, &mapping)?;
+    source_changes.extend(rewrite_parent_imports(
+        crate_root,
+        crate_modules,
+        &mapping,
+        target_crate_name,
+    )?);
+    source_changes.extend(remove_module_declarations(
+        crate_root,
+        crate_modules,
+        &mapping,
+    )?);
+    warnings.extend(dropped_declaration_attributes(
+        crate_root,
+        crate_modules,
+        &mapping,
+    )?);
+    warnings.extend(macro_path_warnings(
+        crate_root,
+        crate_modules,
+        &mapping,
+        target_crate_name,
+    )?);
+
+    let references = crate_references(crate_root, crate_modules, &mapping)?;
+    let mut dependencies = copy_dependencies(
+        crate_root,
+        target_crate_root,
+        &references.extracted,
+        &references.tests,
+        &crate_modules.crate_aliases,
+    )?;
+    let forwarded = wire_features(
+        crate_root,
+        target_crate_name,
+        &mut dependencies,
+        &references.features,
+    )?;
+    warnings.extend(forwarded.warnings.iter().cloned());
+    let hoisted = if options.hoist_dependencies {
+        let hoisted = hoist_shared_dependencies(
+            crate_root,
+            target_crate_root,
+            &mut dependencies,
+            &references.parent,
+        )?;
+        warnings.extend(hoisted.warnings.iter().cloned());
+        hoisted
+    } else {
+        HoistedDependencies::default()
+    };
+    warnings.extend(pin_versions(
+        crate_root,
+        target_crate_root,
+        &mut dependencies,
+        options.pinning,
+    )?);
+    let manifest_changes = link_target_crate(
+        crate_root,
+        target_crate_name,
+        target_crate_root,
+        &hoisted,
+        &forwarded,
+    )?;
+    source_changes.extend(manifest_changes.changes);
+    warnings.extend(manifest_changes.warnings);
+    let mut created_files = vec![CreatedFile {
+        path: target_crate_root.join("Cargo.toml"),
+        content: target_crate_manifest(
+            target_crate_name,
+            &package_metadata(crate_root, target_crate_root)?,
+            &dependencies,
+        )?,
+    }];
+    let declarations = mapping.lib_rs_declarations();
+    let mut lib_rs = Some(declarations.clone());
+    for lifted in lift_inline_modules(crate_root, crate_modules, &mapping)? {
+        if lifted.new_module.as_str() == "crate" {
+            lib_rs = Some(join_sections(&lifted.content, &declarations));
+        } else {
+            created_files.push(CreatedFile {
+                path: module_file(&target_source_dir, &lifted.new_module, false),
+                content: lifted.content,
+            });
+        }
+    }
+    // The file of a module that becomes the root of the new crate is its `lib.rs`, which must
+    // declare the other extracted subtrees.
+    if let Some((root, _)) = mapping
+        .roots()
+        .find(|(_, new_module)| new_module.as_str() == "crate")
+        && let Some(info) = crate_modules.modules.get(root)
+        && !info.inline
+    {
+        lib_rs = None;
+        if !declarations.is_empty() {
+            let content = read_to_string(crate_root.join(&info.file))?;
+            let mut change = SourceChange::new(info.file.clone());
+            change.push(TextEdit::insert(
+                content.len(),
+                join_sections(&content, &declarations)[content.len()..].to_owned(),
+            ));
+            source_changes.push(change);
+        }
+    }
+    if let Some(lib_rs) = lib_rs {
+        created_files.insert(
+            1,
+            CreatedFile {
+                path: target_source_dir.join("lib.rs"),
+                content: target_crate_lib_rs(&lib_rs).to_owned(),
+            },
+        );
+    }
+    let source_changes = merge_changes(source_changes);
+
+    let mut source_files = source_changes
+        .iter()
+        .map(|change| change.file.clone())
+        .collect::<Vec<_>>();
+    for moved_file in &moved_files {
+        list_files(&moved_file.from, &mut source_files)?;
+    }
+    let source_hashes = source_files
+        .into_iter()
+        .map(|file| {
+            let file = file.strip_prefix(crate_root).unwrap_or(&file).to_owned();
+            let hash = hash_file(&crate_root.join(&file))?;
+            Ok((file, hash))
+        })
+        .collect::<Result<_, std::io::Error>>()?;
+
+    Ok(ExtractionPlan {
+        crate_root: crate_root.to_owned(),
+        target_crate_name: target_crate_name.to_owned(),
+        target_crate_root: target_crate_root.to_owned(),
+        created_files,
+        moved_files,
+        source_changes,
+        warnings,
+        source_hashes,
+    })
+}
+
+/// Where the files of the extracted modules go in the new crate.
+///
+/// Each file is placed according to the new path of its module, so that the `mod` declarations
+/// still resolve: the root of the new crate becomes its `lib.rs`, and `crate::auth::jwt` goes to
+/// `src/auth/jwt.rs`, or `src/auth/jwt/mod.rs` if it was a `mod.rs`.
+fn module_moves(
+    crate_root: &Path,
+    modules: &ModuleTree,
+    mapping: &ModuleMapping,
+    target_source_dir: &Path,
+) -> Vec<MovedFile> {
+    modules
+        .iter()
+        .filter(|(_, info)| !info.inline)
+        .filter_map(|(module, info)| {
+            let new_module = mapping.map(module)?;
+            Some(MovedFile {
+                from: crate_root.join(&info.file),
+                to: module_file(
+                    target_source_dir,
+                    &new_module,
+                    info.file.ends_with("mod.rs"),
+                ),
+            })
+        })
+        .collect()
+}
+
+/// The file of `module` in the source directory of the new crate.
+fn module_file(target_source_dir: &Path, module: &ModuleName, mod_rs: bool) -> PathBuf {
+    match module.as_str().strip_prefix("crate::") {
+        None => target_source_dir.join("lib.rs"),
+        Some(path) => {
+            let file = target_source_dir.join(path.replace("::", "/"));
+            if mod_rs {
+                file.join("mod.rs")
+            } else {
+                file.with_extension("rs")
+            }
+        }
+    }
+}
+
+/// `first` followed by `second`, separated by an empty line if both are not empty.
+fn join_sections(first: &str, second: &str) -> String {
+    if first.trim().is_empty() || second.is_empty() {
+        return format!("{first}{second}");
+    }
+    format!("{}\n\n{second}", first.trim_end())
+}
+
+/// Merges the changes to the same file, keeping the order in which the files first appear.
+fn merge_changes(changes: Vec<SourceChange>) -> Vec<SourceChange> {
+    let mut merged: Vec<SourceChange> = Vec::new();
+    for change in changes {
+        match merged.iter_mut().find(|other| other.file == change.file) {
+            Some(other) => other.edits.extend(change.edits),
+            None => merged.push(change),
+        }
+    }
+    for change in &mut merged {
+        change
+            .edits
+            .sort_by_key(|edit| (edit.range.start, edit.range.end));
+    }
+    merged
+}
+
+/// Where the journal of the last extraction from the crate at `crate_root` is saved, in the
+/// target directory of its workspace.
+pub fn journal_path(crate_root: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    Ok(target_directory(crate_root)?
+        .join("extricrate")
+        .join(get_crate_name(crate_root)?)
+        .join("journal.json"))
+}
+
+/// Reverts the last extraction from the crate at `crate_root`, unless the files it wrote were
+/// modified since. Returns the path of the journal it was reverted from.
+pub fn undo_last_extraction(crate_root: &Path) -> Result<PathBuf, Box<dyn Error>> {
+    let journal_path = journal_path(crate_root)?;
+    match Journal::load(&journal_path)?.undo() {
+        Ok(()) => fs::remove_file(&journal_path)?,
+        // Only the steps that failed are left to undo.
+        Err(error @ JournalError::Incomplete { .. }) => {
+            if let JournalError::Incomplete { remaining, .. } = &error {
+                remaining.save(&journal_path)?;
+            }
+            return Err(format!("{error} (journal: {})", journal_path.display()).into());
+        }
+        Err(error) => return Err(error.into()),
+    }
+    Ok(journal_path)
+}
+
+impl ExtractionPlan {
+    /// Performs the extraction: creates the new crate, edits the files and moves them.
+    ///
+    /// Refuses to run if any source file changed since the plan was computed. All the edits
+    /// are computed before anything is written, and if writing fails halfway, everything
+    /// written so far is rolled back. The journal to undo the extraction with
+    /// [undo_last_extraction] is saved as part of it, and its path returned.
+    pub fn apply(&self) -> Result<PathBuf, Box<dyn Error>> {
+        self.check_sources()?;
+        let journal_path = journal_path(&self.crate_root)?;
+        let edited_files = self
+            .source_changes
+            .iter()
+            .map(|change| {
+                let path = self.crate_root.join(&change.file);
+                let content = change.apply(&read_to_string(&path)?)?;
+                Ok((path, content))
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+
+        Transaction::run_and_save(&journal_path, |transaction| {
+            for file in &self.created_files {
+                transaction.write(&file.path, &file.content)?;
+            }
+            for (path, content) in &edited_files {
+                transaction.write(path, content)?;
+            }
+            for moved_file in &self.moved_files {
+                transaction.rename(&moved_file.from, &moved_file.to)?;
+            }
+            // The directories of the extracted modules, unless other files were left in them.
+            let source_dir = self.crate_root.join("src");
+            for moved_file in &self.moved_files {
+                for directory in moved_file.from.ancestors().skip(1) {
+                    if !directory.starts_with(&source_dir)
+                        || directory == source_dir
+                        || !directory
+                            .read_dir()
+                            .is_ok_and(|mut entries| entries.next().is_none())
+                    {
+                        break;
+                    }
+                    transaction.remove_dir(directory)?;
+                }
+            }
+            Ok::<_, Box<dyn Error>>(())
+        })?;
+        Ok(journal_path)
+    }
+
+    /// Locates the edits of the extraction in the files as they will be once it is applied, to
+    /// attribute compilation errors to them. Must be called before [ExtractionPlan::apply].
+    pub fn applied_edits(&self) -> Result<Vec<AppliedEdit>, Box<dyn Error>> {
+        let mut applied_edits = Vec::new();
+        for file in &self.created_files {
+            if file
+                .path
+                .extension()
+                .is_some_and(|extension| extension == "rs")
+            {
+                applied_edits.push(AppliedEdit::new(
+                    file.path.clone(),
+                    &file.content,
+                    0,
+                    file.content.len(),
+                ));
+            }
+        }
+        for change in &self.source_changes {
+            let content = read_to_string(self.crate_root.join(&change.file))?;
+            let new_content = change.apply(&content)?;
+            let path = self.final_path(&self.crate_root.join(&change.file));
+            let mut edits = change.edits.iter().collect::<Vec<_>>();
+            edits.sort_by_key(|edit| (edit.range.start, edit.range.end));
+            // Offset of the edits in the new content, from the length of the previous ones.
+            let mut shift = 0isize;
+            for edit in edits {
+                let start = edit.range.start.saturating_add_signed(shift);
+                applied_edits.push(AppliedEdit::new(
+                    path.clone(),
+                    &new_content,
+                    start,
+                    start + edit.text.len(),
+                ));
+                shift += edit.text.len() as isize - edit.range.len() as isize;
+            }
+        }
+        Ok(applied_edits)
+    }
+
+    /// Where `path`, in the parent crate, is once the files are moved.
+    fn final_path(&self, path: &Path) -> PathBuf {
+        self.moved_files
+            .iter()
+            .find_map(|moved_file| {
+                let relative = path.strip_prefix(&moved_file.from).ok()?;
+                Some(if relative.as_os_str().is_empty() {
+                    moved_file.to.clone()
+                } else {
+                    moved_file.to.join(relative)
+                })
+            })
+            .unwrap_or_else(|| path.to_owned())
+    }
+
+    /// Checks that the source files are the ones the plan was computed from.
+    pub fn check_sources(&self) -> Result<(), PlanError> {
+        for (file, hash) in &self.source_hashes {
+            let current_hash = hash_file(&self.crate_root.join(file))
+                .map_err(|_| PlanError::SourceMissing(file.clone()))?;
+            if &current_hash != hash {
+                return Err(PlanError::SourceChanged(file.clone()));
+            }
+        }
+        Ok(())
+    }
+
+    /// Describes the extraction for review: the created and moved files, then a unified diff of
+    /// every created or edited file. Paths are shown relative to the parent crate.
+    pub fn to_diff(&self) -> Result<String, Box<dyn Error>> {
+        let mut text = String::new();
+        writeln!(
+            text,
+            "New crate {} in {}",
+            self.target_crate_name,
+            self.display_path(&self.target_crate_root).display()
+        )?;
+        if !self.warnings.is_empty() {
+            text.push_str("\nWarnings:\n");
+            for warning in &self.warnings {
+                writeln!(text, "    {warning}")?;
+            }
+        }
+        text.push_str("\nCreated files:\n");
+        for file in &self.created_files {
+            writeln!(text, "    {}", self.display_path(&file.path).display())?;
+        }
+        if !self.moved_files.is_empty() {
+            text.push_str("\nMoved files:\n");
+            for moved_file in &self.moved_files {
+                writeln!(
+                    text,
+                    "    {} -> {}",
+                    self.display_path(&moved_file.from).display(),
+                    self.display_path(&moved_file.to).display()
+                )?;
+            }
+        }
+        for file in &self.created_files {
+            text.push('\n');
+            text.push_str(&unified_diff(
+                &self.display_path(&file.path),
+                "",
+                &file.content,
+            ));
+        }
+        for change in &self.source_changes {
+            let content = read_to_string(self.crate_root.join(&change.file))?;
+            text.push('\n');
+            text.push_str(&change.diff(&content)?);
+        }
+        Ok(text)
+    }
+
+    /// `path` relative to the parent crate when possible, without `.` components.
+    fn display_path(&self, path: &Path) -> PathBuf {
+        path.strip_prefix(&self.crate_root)
+            .unwrap_or(path)
+            .components()
+            .filter(|component| component != &Component::CurDir)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{self, read_to_string, remove_dir_all};
+    use std::path::{Path, PathBuf};
+
+    use pretty_assertions::assert_eq;
+
+    use crate::dependencies::{ModuleName, ModulePath, list_modules};
+    use crate::edit::{SourceChange, TextEdit};
+    use crate::rewrite::RewriteError;
+    use crate::verify::AppliedEdit;
+
+    use super::{
+        ExtractionOptions, ExtractionPlan, MovedFile, PlanError, journal_path, plan_extraction,
+        undo_last_extraction,
+    };
+
+    fn layered_util_plan() -> ExtractionPlan {
+        let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/layered");
+        let crate_modules = list_modules(&crate_root).unwrap();
+        plan_extraction(
+            &crate_root,
+            &[ModulePath::from("util")],
+            "layered-util",
+            &crate_root.join("../layered-util"),
+            &crate_modules,
+            &ExtractionOptions::default(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn plans_extraction() {
+        let plan = layered_util_plan();
+        let crate_root = &plan.crate_root;
+        let target_crate_root = crate_root.join("../layered-util");
+        assert_eq!(
+            plan.moved_files,
+            vec![MovedFile {
+                from: crate_root.join("src/util.rs"),
+                to: target_crate_root.join("src/lib.rs"),
+            }]
+        );
+        assert_eq!(
+            plan.created_files
+                .iter()
+                .map(|file| &file.path)
+                .collect::<Vec<_>>(),
+            vec![&target_crate_root.join("Cargo.toml")]
+        );
+        assert_eq!(
+            plan.source_changes,
+            vec![
+                SourceChange {
+                    file: PathBuf::from("src/api/mod.rs"),
+                    edits: vec![TextEdit::replace(27..51, "use layered_util::format;")],
+                },
+                SourceChange {
+                    file: PathBuf::from("src/db.rs"),
+                    edits: vec![TextEdit::replace(0..20, "use layered_util::Id;")],
+                },
+                SourceChange {
+                    file: PathBuf::from("src/main.rs"),
+                    edits: vec![TextEdit::delete(17..27)],
+                },
+                SourceChange {
+                    file: PathBuf::from("Cargo.toml"),
+                    edits: vec![TextEdit::insert(
+                        90,
+                        "layered-util = { path = \"../layered-util\" }\n"
+                    )],
+                },
+            ]
+        );
+        assert_eq!(
+            plan.source_hashes.keys().collect::<Vec<_>>(),
+            vec![
+                Path::new("Cargo.toml"),
+                Path::new("src/api/mod.rs"),
+                Path::new("src/db.rs"),
+                Path::new("src/main.rs"),
+                Path::new("src/util.rs")
+            ]
+        );
+    }
+
+    fn copy_dir(from: &Path, to: &Path) {
+        fs::create_dir_all(to).unwrap();
+        for entry in fs::read_dir(from).unwrap() {
+            let path = entry.unwrap().path();
+            let target = to.join(path.file_name().unwrap());
+            if path.is_dir() {
+                copy_dir(&path, &target);
+            } else {
+                fs::copy(&path, &target).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn rolls_back_failed_extraction() {
+        let root = temp_dir().join("extricrate_plan_rollback");
+        remove_dir_all(&root).unwrap_or_default();
+        let crate_root = root.join("layered");
+        copy_dir(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/layered"),
+            &crate_root,
+        );
+        let target_crate_root = root.join("layered-util");
+        let plan = plan_extraction(
+            &crate_root,
+            &[ModulePath::from("util")],
+            "layered-util",
+            &target_crate_root,
+            &list_modules(&crate_root).unwrap(),
+            &ExtractionOptions::default(),
+        )
+        .unwrap();
+        // The move is the last step, and fails since its target already exists.
+        fs::create_dir_all(target_crate_root.join("src")).unwrap();
+        fs::write(target_crate_root.join("src/lib.rs"), "").unwrap();
+
+        assert!(plan.apply().is_err());
+        assert_eq!(
+            read_to_string(crate_root.join("src/db.rs")).unwrap(),
+            read_to_string(
+                Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/layered/src/db.rs")
+            )
+            .unwrap()
+        );
+        assert!(crate_root.join("src/util.rs").exists());
+        assert!(!target_crate_root.join("Cargo.toml").exists());
+        assert_eq!(
+            read_to_string(target_crate_root.join("src/lib.rs")).unwrap(),
+            ""
+        );
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn turns_module_into_crate_root() {
+        let root = temp_dir().join("extricrate_plan_crate_root");
+        remove_dir_all(&root).unwrap_or_default();
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/nested");
+        let crate_root = root.join("nested");
+        copy_dir(&fixture, &crate_root);
+        let target_crate_root = root.join("nested-auth");
+        let plan = plan_extraction(
+            &crate_root,
+            &[ModulePath::from("auth")],
+            "nested-auth",
+            &target_crate_root,
+            &list_modules(&crate_root).unwrap(),
+            &ExtractionOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            plan.moved_files,
+            ["mod.rs", "jwt.rs", "session.rs"]
+                .map(|file| MovedFile {
+                    from: crate_root.join("src/auth").join(file),
+                    to: target_crate_root
+                        .join("src")
+                        .join(file.replace("mod.rs", "lib.rs")),
+                })
+                .to_vec()
+        );
+
+        plan.apply().unwrap();
+        assert!(!crate_root.join("src/auth").exists());
+        assert_eq!(
+            read_to_string(target_crate_root.join("src/lib.rs")).unwrap(),
+            read_to_string(fixture.join("src/auth/mod.rs"))
+                .unwrap()
+                .replace("crate::auth::jwt", "crate::jwt")
+        );
+        assert!(target_crate_root.join("src/session.rs").exists());
+        assert_eq!(
+            read_to_string(crate_root.join("src/lib.rs")).unwrap(),
+            "pub fn check(token: &str) -> bool {\n    nested_auth::verify(token).is_valid()\n}\n"
+        );
+        undo_last_extraction(&crate_root).unwrap();
+        assert!(crate_root.join("src/auth/mod.rs").exists());
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn moves_submodules_next_to_module_file() {
+        let root = temp_dir().join("extricrate_plan_modern");
+        remove_dir_all(&root).unwrap_or_default();
+        let crate_root = root.join("modern");
+        copy_dir(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/modern"),
+            &crate_root,
+        );
+        let target_crate_root = root.join("modern-auth");
+        let plan = plan_extraction(
+            &crate_root,
+            &[ModulePath::from("auth")],
+            "modern-auth",
+            &target_crate_root,
+            &list_modules(&crate_root).unwrap(),
+            &ExtractionOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            plan.moved_files,
+            [
+                ("auth.rs", "lib.rs"),
+                ("auth/jwt.rs", "jwt.rs"),
+                ("auth/session.rs", "session.rs"),
+                ("auth/session/store.rs", "session/store.rs"),
+            ]
+            .map(|(from, to)| MovedFile {
+                from: crate_root.join("src").join(from),
+                to: target_crate_root.join("src").join(to),
+            })
+            .to_vec()
+        );
+
+        plan.apply().unwrap();
+        assert!(!crate_root.join("src/auth").exists());
+        assert!(!crate_root.join("src/auth.rs").exists());
+        assert!(target_crate_root.join("src/session/store.rs").exists());
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn lifts_inline_module() {
+        let root = temp_dir().join("extricrate_plan_lifted");
+        remove_dir_all(&root).unwrap_or_default();
+        let crate_root = root.join("lifted");
+        copy_dir(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/lifted"),
+            &crate_root,
+        );
+        let target_crate_root = root.join("lifted-auth");
+        let plan = plan_extraction(
+            &crate_root,
+            &[ModulePath::from("auth")],
+            "lifted-auth",
+            &target_crate_root,
+            &list_modules(&crate_root).unwrap(),
+            &ExtractionOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(
+            plan.moved_files,
+            vec![MovedFile {
+                from: crate_root.join("src/auth/jwt.rs"),
+                to: target_crate_root.join("src/jwt.rs"),
+            }]
+        );
+
+        plan.apply().unwrap();
+        assert_eq!(
+            read_to_string(target_crate_root.join("src/lib.rs")).unwrap(),
+            concat!(
+                "//! Authentication.\n",
+                "\n",
+                "pub mod jwt;\n",
+                "\n",
+                "pub fn verify(token: &str) -> bool {\n",
+                "    crate::jwt::decode(token)\n",
+                "}\n",
+            )
+        );
+        assert_eq!(
+            read_to_string(crate_root.join("src/lib.rs")).unwrap(),
+            "pub fn check(token: &str) -> bool {\n    lifted_auth::verify(token)\n}\n"
+        );
+        assert!(!crate_root.join("src/auth").exists());
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn undoes_last_extraction() {
+        let root = temp_dir().join("extricrate_plan_undo");
+        remove_dir_all(&root).unwrap_or_default();
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/layered");
+        let crate_root = root.join("layered");
+        copy_dir(&fixture, &crate_root);
+        let target_crate_root = root.join("layered-util");
+        plan_extraction(
+            &crate_root,
+            &[ModulePath::from("util")],
+            "layered-util",
+            &target_crate_root,
+            &list_modules(&crate_root).unwrap(),
+            &ExtractionOptions::default(),
+        )
+        .unwrap()
+        .apply()
+        .unwrap();
+        assert!(target_crate_root.join("src/lib.rs").exists());
+        assert!(journal_path(&crate_root).unwrap().exists());
+
+        undo_last_extraction(&crate_root).unwrap();
+        assert_eq!(
+            read_to_string(crate_root.join("src/db.rs")).unwrap(),
+            read_to_string(fixture.join("src/db.rs")).unwrap()
+        );
+        assert!(crate_root.join("src/util.rs").exists());
+        assert!(!target_crate_root.exists());
+        assert!(!journal_path(&crate_root).unwrap().exists());
+        assert!(undo_last_extraction(&crate_root).is_err());
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn undoes_what_it_can_around_untracked_files() {
+        let root = temp_dir().join("extricrate_plan_undo_untracked");
+        remove_dir_all(&root).unwrap_or_default();
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/layered");
+        let crate_root = root.join("layered");
+        copy_dir(&fixture, &crate_root);
+        let target_crate_root = root.join("layered-util");
+        plan_extraction(
+            &crate_root,
+            &[ModulePath::from("util")],
+            "layered-util",
+            &target_crate_root,
+            &list_modules(&crate_root).unwrap(),
+            &ExtractionOptions::default(),
+        )
+        .unwrap()
+        .apply()
+        .unwrap();
+        // Building the new crate on its own creates files the extraction doesn't know about.
+        fs::write(target_crate_root.join("Cargo.lock"), "").unwrap();
+
+        assert!(undo_last_extraction(&crate_root).is_err());
+        assert_eq!(
+            read_to_string(crate_root.join("src/util.rs")).unwrap(),
+            read_to_string(fixture.join("src/util.rs")).unwrap()
+        );
+        assert!(journal_path(&crate_root).unwrap().exists());
+
+        fs::remove_file(target_crate_root.join("Cargo.lock")).unwrap();
+        undo_last_extraction(&crate_root).unwrap();
+        assert!(!target_crate_root.exists());
+        assert!(!journal_path(&crate_root).unwrap().exists());
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn locates_applied_edits() {
+        let plan = layered_util_plan();
+        let target_crate_root = plan.crate_root.join("../layered-util");
+        assert_eq!(
+            plan.applied_edits().unwrap(),
+            vec![
+                AppliedEdit {
+                    file: plan.crate_root.join("src/api/mod.rs"),
+                    lines: 2..=2,
+                },
+                AppliedEdit {
+                    file: plan.crate_root.join("src/db.rs"),
+                    lines: 1..=1,
+                },
+                AppliedEdit {
+                    file: plan.crate_root.join("src/main.rs"),
+                    lines: 3..=3,
+                },
+                AppliedEdit {
+                    file: plan.crate_root.join("Cargo.toml"),
+                    lines: 8..=8,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn round_trips_through_json() {
+        let plan = layered_util_plan();
+        let json = serde_json::to_string(&plan).unwrap();
+        assert_eq!(serde_json::from_str::<ExtractionPlan>(&json).unwrap(), plan);
+    }
+
+    #[test]
+    fn refuses_modules_declared_with_a_path() {
+        let root = temp_dir().join("extricrate_plan_path_attribute");
+        remove_dir_all(&root).unwrap_or_default();
+        let crate_root = root.join("nested");
+        copy_dir(
+            &Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/nested"),
+            &crate_root,
+        );
+        let auth = crate_root.join("src/auth/mod.rs");
+        let content = read_to_string(&auth).unwrap();
+        fs::write(
+            &auth,
+            content.replace("mod session;", "#[path = \"session.rs\"]\nmod session;"),
+        )
+        .unwrap();
+
+        let error = plan_extraction(
+            &crate_root,
+            &[ModulePath::from("auth")],
+            "nested-auth",
+            &root.join("nested-auth"),
+            &list_modules(&crate_root).unwrap(),
+            &ExtractionOptions::default(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<RewriteError>(),
+            Some(&RewriteError::PathAttribute {
+                file: PathBuf::from("src/auth/mod.rs"),
+                module: ModuleName::from("crate::auth::session"),
+            })
+        );
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn refuses_changed_sources() {
+        let mut plan = layered_util_plan();
+        assert_eq!(plan.check_sources(), Ok(()));
+        plan.source_hashes
+            .insert(PathBuf::from("src/db.rs"), "0".repeat(64));
+        assert_eq!(
+            plan.check_sources(),
+            Err(PlanError::SourceChanged(PathBuf::from("src/db.rs")))
+        );
+        assert!(plan.apply().is_err());
+        let mut plan = layered_util_plan();
+        plan.source_hashes
+            .insert(PathBuf::from("src/missing.rs"), "0".repeat(64));
+        assert_eq!(
+            plan.check_sources(),
+            Err(PlanError::SourceMissing(PathBuf::from("src/missing.rs")))
+        );
+    }
+
+    #[test]
+    fn describes_extraction_as_diff() {
+        let diff = layered_util_plan().to_diff().unwrap();
+        assert_eq!(
+            diff,
+            concat!(
+                "New crate layered-util in ../layered-util\n",
+                "\n",
+                "Created files:\n",
+                "    ../layered-util/Cargo.toml\n",
+                "\n",
+                "Moved files:\n",
+                "    src/util.rs -> ../layered-util/src/lib.rs\n",
+                "\n",
+                "--- /dev/null\n",
+                "+++ b/../layered-util/Cargo.toml\n",
+                "@@ -0,0 +1,4 @@\n",
+                "+[package]\n",
+                "+name = \"layered-util\"\n",
+                "+version = \"0.0.1\"\n",
+                "+edition = \"2024\"\n",
+                "\n",
+                "--- a/src/api/mod.rs\n",
+                "+++ b/src/api/mod.rs\n",
+                "@@ -1,5 +1,5 @@\n",
+                " use crate::db::Connection;\n",
+                "-use crate::util::format;\n",
+                "+use layered_util::format;\n",
+                " use serde::Serialize;\n",
+                " \n",
+                " mod handlers;\n",
+                "\n",
+                "--- a/src/db.rs\n",
+                "+++ b/src/db.rs\n",
+                "@@ -1,4 +1,4 @@\n",
+                "-use crate::util::Id;\n",
+                "+use layered_util::Id;\n",
+                " \n",
+                " pub struct Connection {\n",
+                "     id: Id,\n",
+                "\n",
+                "--- a/src/main.rs\n",
+                "+++ b/src/main.rs\n",
+                "@@ -1,6 +1,5 @@\n",
+                " mod api;\n",
+                " mod db;\n",
+                "-mod util;\n",
+                " \n",
+                " use crate::api::Server;\n",
+                " \n",
+                "\n",
+                "--- a/Cargo.toml\n",
+                "+++ b/Cargo.toml\n",
+                "@@ -5,3 +5,4 @@\n",
+                " \n",
+                " [dependencies]\n",
+                " serde = \"1\"\n",
+                "+layered-util = { path = \"../layered-util\" }\n",
+            )
+        );
+    }
+}
@@ -0,0 +1,1863 @@
+use std::fs::read_to_string;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use proc_macro2::{Spacing, Span, TokenStream, TokenTree};
+use syn::{
+    Attribute, Item, ItemMod, ItemUse, Macro, UseGroup, UseName, UsePath, UseRename, UseTree,
+    spanned::Spanned,
+    visit::{self, Visit},
+};
+use thiserror::Error;
+
+use crate::dependencies::{CrateModules, ModuleName, ModuleTree, UseStatement};
+use crate::edit::{SourceChange, TextEdit, apply_edits};
+use crate::refactor::ModuleMapping;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum RewriteError {
+    #[error("file not readable: {0}")]
+    FileNotReadable(PathBuf),
+    #[error("file not parsable: {0}")]
+    FileNotParsable(PathBuf),
+    #[error("`super` in {module} ({file}) points outside of the extracted modules")]
+    SuperOutsideOfExtractedModules { file: PathBuf, module: ModuleName },
+    #[error(
+        "cannot re-root an import of {module} in {file}: move it to a separate `use` statement"
+    )]
+    UnsupportedImport { file: PathBuf, module: ModuleName },
+    #[error(
+        "`#[path]` on the declaration of {module} in {file} is not supported: move the module to its default location"
+    )]
+    PathAttribute { file: PathBuf, module: ModuleName },
+}
+
+/// Applies edits computed on a snippet of source text, which can't overlap by construction.
+fn splice(text: &str, offset: usize, edits: &[TextEdit]) -> String {
+    apply_edits(Path::new(""), text, offset, edits).expect("edits of a snippet don't overlap")
+}
+
+/// How paths to the extracted modules are rewritten.
+struct Context<'a> {
+    mapping: &'a ModuleMapping,
+    /// Name of the new crate, as used in paths.
+    crate_name: String,
+    modules: &'a ModuleTree,
+}
+
+impl Context<'_> {
+    /// The path of `module` from the parent crate, if it is extracted.
+    fn external_path(&self, module: &[String]) -> Option<String> {
+        let new_module = self.mapping.map(&ModuleName::from(module.join("::")))?;
+        let suffix = new_module
+            .as_str()
+            .strip_prefix("crate")
+            .unwrap_or_default();
+        Some(format!("{}{suffix}", self.crate_name))
+    }
+
+    fn is_extracted(&self, module: &ModuleName) -> bool {
+        self.mapping.map(module).is_some()
+    }
+
+    /// Resolves the first segment of a path written in the module `ancestors` to an absolute
+    /// module path, or `None` if it refers to another crate or to an item.
+    fn resolve_first_segment(&self, ancestors: &[String], ident: &str) -> Option<Vec<String>> {
+        let mut absolute = vec!["crate".to_owned()];
+        match ident {
+            "crate" => {}
+            "self" => absolute.extend_from_slice(ancestors),
+            "super" => {
+                absolute.extend_from_slice(ancestors);
+                absolute.pop();
+            }
+            _ => {
+                // Since Rust 2018, a path can start with a submodule of the current module.
+                absolute.extend_from_slice(ancestors);
+                absolute.push(ident.to_owned());
+                if !self
+                    .modules
+                    .contains_key(&ModuleName::from(absolute.join("::")))
+                {
+                    return None;
+                }
+            }
+        }
+        Some(absolute)
+    }
+
+    /// The deepest module of the crate that a path written in the module `ancestors` goes
+    /// through, or `None` if it doesn't start with a module of the crate.
+    fn resolve_module(&self, ancestors: &[String], path: &syn::Path) -> Option<ModuleName> {
+        let mut segments = path.segments.iter();
+        let first = segments.next()?;
+        let mut module = self.resolve_first_segment(ancestors, &first.ident.to_string())?;
+        // The last segment is an item rather than a module.
+        for segment in segments.take(path.segments.len().saturating_sub(2)) {
+            let mut next = module.clone();
+            if segment.ident == "super" {
+                next.pop();
+            } else {
+                next.push(segment.ident.to_string());
+            }
+            if !self.modules.contains_key(&to_module_name(&next)) {
+                break;
+            }
+            module = next;
+        }
+        Some(to_module_name(&module))
+    }
+
+    /// Rewrites a `use` item, given as source text. Returns `None` if it doesn't import
+    /// anything from the extracted modules.
+    fn rewrite_use_item(&self, source: &str, ancestors: &[String], indent: &str) -> Option<String> {
+        let item = syn::parse_str::<ItemUse>(source).ok()?;
+        if item.leading_colon.is_some() {
+            return None;
+        }
+        let tree_range = item.tree.span().byte_range();
+        let prefix = &source[..tree_range.start];
+        let split = self.split_use_tree(source, &item.tree, None, ancestors);
+        let (kept, moved) = match split {
+            Split::Keep => return None,
+            Split::Move(moved) => (None, moved),
+            Split::Partial { kept, moved } => (Some(kept), moved),
+        };
+        let statements = kept
+            .into_iter()
+            .chain(moved)
+            .map(|tree| format!("{prefix}{tree};"))
+            .collect::<Vec<_>>();
+        Some(statements.join(&format!("\n{indent}")))
+    }
+
+    /// Splits a use tree between what stays in the parent crate and what moves to the new
+    /// crate. `prefix` is the absolute module path of the tree, `None` at the start of the path.
+    fn split_use_tree(
+        &self,
+        source: &str,
+        tree: &UseTree,
+        prefix: Option<&[String]>,
+        ancestors: &[String],
+    ) -> Split {
+        let text = |span: Span| &source[span.byte_range()];
+        match tree {
+            UseTree::Path(UsePath {
+                ident,
+                tree: subtree,
+                ..
+            }) => {
+                let ident = ident.to_string();
+                let new_prefix = match prefix {
+                    None => self.resolve_first_segment(ancestors, &ident),
+                    Some(prefix) if ident == "super" => {
+                        let mut new_prefix = prefix.to_vec();
+                        new_prefix.pop();
+                        Some(new_prefix)
+                    }
+                    Some(prefix) => {
+                        let mut new_prefix = prefix.to_vec();
+                        new_prefix.push(ident);
+                        Some(new_prefix)
+                    }
+                };
+                let Some(new_prefix) = new_prefix else {
+                    return Split::Keep;
+                };
+                if let Some(external_path) = self.external_path(&new_prefix) {
+                    let subtree_text = self.import_self_by_name(source, subtree, &new_prefix);
+                    return Split::Move(vec![format!("{external_path}::{subtree_text}")]);
+                }
+                match self.split_use_tree(source, subtree, Some(&new_prefix), ancestors) {
+                    Split::Partial { kept, moved } => {
+                        let path_start = tree.span().byte_range().start;
+                        let subtree_start = subtree.span().byte_range().start;
+                        Split::Partial {
+                            kept: format!("{}{kept}", &source[path_start..subtree_start]),
+                            moved,
+                        }
+                    }
+                    split => split,
+                }
+            }
+            UseTree::Name(UseName { ident }) => {
+                let Some(prefix) = prefix else {
+                    return Split::Keep;
+                };
+                let mut module = prefix.to_vec();
+                module.push(ident.to_string());
+                match self.external_path(&module) {
+                    Some(external_path) if ident != "self" => {
+                        Split::Move(vec![rename(external_path, &ident.to_string())])
+                    }
+                    _ => Split::Keep,
+                }
+            }
+            UseTree::Rename(UseRename {
+                ident, rename: to, ..
+            }) => {
+                let Some(prefix) = prefix else {
+                    return Split::Keep;
+                };
+                let mut module = prefix.to_vec();
+                if ident != "self" {
+                    module.push(ident.to_string());
+                }
+                match self.external_path(&module) {
+                    Some(external_path) => {
+                        Split::Move(vec![rename(external_path, &to.to_string())])
+                    }
+                    None => Split::Keep,
+                }
+            }
+            UseTree::Glob(_) => Split::Keep,
+            UseTree::Group(UseGroup { items, .. }) => {
+                let splits = items
+                    .iter()
+                    .map(|item| self.split_use_tree(source, item, prefix, ancestors))
+                    .collect::<Vec<_>>();
+                if splits.iter().all(|split| matches!(split, Split::Keep)) {
+                    return Split::Keep;
+                }
+                if splits.iter().all(|split| matches!(split, Split::Move(_))) {
+                    return Split::Move(splits.into_iter().flat_map(Split::into_moved).collect());
+                }
+                let kept_indices = splits
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, split)| !matches!(split, Split::Move(_)))
+                    .map(|(index, _)| index)
+                    .collect::<Vec<_>>();
+                let last_kept = *kept_indices.last().expect("some items are kept");
+                let ranges = items
+                    .iter()
+                    .map(|item| item.span().byte_range())
+                    .collect::<Vec<_>>();
+                let mut edits = Vec::new();
+                for (index, split) in splits.iter().enumerate() {
+                    match split {
+                        Split::Keep => {}
+                        Split::Partial { kept, .. } => edits.push(TextEdit {
+                            range: ranges[index].clone(),
+                            text: kept.clone(),
+                        }),
+                        // Remove the item along with the separator after it, or before it for
+                        // the items after the last kept one.
+                        Split::Move(_) if index < last_kept => edits.push(TextEdit {
+                            range: ranges[index].start..ranges[index + 1].start,
+                            text: String::new(),
+                        }),
+                        Split::Move(_) => edits.push(TextEdit {
+                            range: ranges[index - 1].end..ranges[index].end,
+                            text: String::new(),
+                        }),
+                    }
+                }
+                // Consecutive removals after the last kept item overlap: merge them.
+                edits.sort_by_key(|edit| edit.range.start);
+                let mut merged: Vec<TextEdit> = Vec::new();
+                for edit in edits {
+                    match merged.last_mut() {
+                        Some(last)
+                            if last.text.is_empty()
+                                && edit.text.is_empty()
+                                && edit.range.start < last.range.end =>
+                        {
+                            last.range.end = last.range.end.max(edit.range.end);
+                        }
+                        _ => merged.push(edit),
+                    }
+                }
+                let kept = if let [index] = kept_indices[..]
+                    && !matches!(&items[index], UseTree::Name(UseName { ident }) if ident == "self")
+                {
+                    match &splits[index] {
+                        Split::Partial { kept, .. } => kept.clone(),
+                        _ => text(items[index].span()).to_owned(),
+                    }
+                } else {
+                    let group_range = tree.span().byte_range();
+                    splice(&source[group_range.clone()], group_range.start, &merged)
+                };
+                Split::Partial {
+                    kept,
+                    moved: splits.into_iter().flat_map(Split::into_moved).collect(),
+                }
+            }
+        }
+    }
+
+    /// The text of `subtree`, where a `self` import of a module that becomes the new crate root
+    /// is renamed to keep the module name, since `use new_crate::{self}` would import it under
+    /// the crate name.
+    fn import_self_by_name(&self, source: &str, subtree: &UseTree, module: &[String]) -> String {
+        let range = subtree.span().byte_range();
+        let text = &source[range.clone()];
+        let is_crate_root = self
+            .mapping
+            .map(&ModuleName::from(module.join("::")))
+            .is_some_and(|new_module| new_module.parent().is_none());
+        let UseTree::Group(UseGroup { items, .. }) = subtree else {
+            return text.to_owned();
+        };
+        if !is_crate_root {
+            return text.to_owned();
+        }
+        let name = module.last().cloned().unwrap_or_default();
+        let edits = items
+            .iter()
+            .filter_map(|item| match item {
+                UseTree::Name(UseName { ident }) if ident == "self" => Some(TextEdit {
+                    range: ident.span().byte_range(),
+                    text: format!("self as {name}"),
+                }),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        splice(text, range.start, &edits)
+    }
+}
+
+fn rename(external_path: String, name: &str) -> String {
+    if external_path.rsplit("::").next() == Some(name) {
+        external_path
+    } else {
+        format!("{external_path} as {name}")
+    }
+}
+
+#[derive(Debug)]
+enum Split {
+    /// Nothing in the tree points to the extracted modules.
+    Keep,
+    /// Everything in the tree points to the extracted modules, as these new paths.
+    Move(Vec<String>),
+    /// Part of the tree points to the extracted modules.
+    Partial { kept: String, moved: Vec<String> },
+}
+
+impl Split {
+    fn into_moved(self) -> Vec<String> {
+        match self {
+            Split::Keep => Vec::new(),
+            Split::Move(moved) | Split::Partial { moved, .. } => moved,
+        }
+    }
+}
+
+/// Finds the fully-qualified paths to the extracted modules outside of `use` statements.
+struct PathVisitor<'a> {
+    context: &'a Context<'a>,
+    ancestors: Vec<String>,
+    edits: Vec<TextEdit>,
+}
+
+impl<'ast> Visit<'ast> for PathVisitor<'_> {
+    fn visit_item_mod(&mut self, node: &'ast ItemMod) {
+        self.ancestors.push(node.ident.to_string());
+        let module = ModuleName::from(format!("crate::{}", self.ancestors.join("::")));
+        if !self.context.is_extracted(&module) {
+            visit::visit_item_mod(self, node);
+        }
+        self.ancestors.pop();
+    }
+
+    fn visit_item_use(&mut self, _: &'ast ItemUse) {
+        // Rewritten from the spans of the use statement map.
+    }
+
+    fn visit_path(&mut self, node: &'ast syn::Path) {
+        // A single segment outside of a `use` is a local item or variable, never a module.
+        if node.leading_colon.is_none() && node.segments.len() > 1 {
+            self.rewrite_path(node);
+        }
+        visit::visit_path(self, node);
+    }
+}
+
+impl PathVisitor<'_> {
+    fn rewrite_path(&mut self, path: &syn::Path) {
+        let mut segments = path.segments.iter();
+        let Some(first) = segments.next() else {
+            return;
+        };
+        let Some(mut module) = self
+            .context
+            .resolve_first_segment(&self.ancestors, &first.ident.to_string())
+        else {
+            return;
+        };
+        let start = first.ident.span().byte_range().start;
+        let mut end = first.ident.span().byte_range().end;
+        let mut segments = std::iter::once(first).chain(segments).peekable();
+        segments.next();
+        loop {
+            if let Some(external_path) = self.context.external_path(&module) {
+                self.edits.push(TextEdit {
+                    range: start..end,
+                    text: external_path,
+                });
+                return;
+            }
+            // The last segment is an item rather than a module.
+            let Some(segment) = segments.next() else {
+                return;
+            };
+            if segments.peek().is_none() || !segment.arguments.is_none() {
+                return;
+            }
+            if segment.ident == "super" {
+                module.pop();
+            } else {
+                module.push(segment.ident.to_string());
+            }
+            end = segment.ident.span().byte_range().end;
+        }
+    }
+}
+
+/// A path segment, with its byte range.
+type Segment = (String, Range<usize>);
+
+/// Leading segments of a path to replace so that it keeps pointing to the same module once moved
+/// to the new crate.
+#[derive(Debug)]
+struct Rerooted {
+    /// Number of leading segments to replace.
+    consumed: usize,
+    /// Module they point to in the new crate.
+    module: Vec<String>,
+    /// Text replacing them, relative to the prefix of the path if any.
+    text: String,
+}
+
+/// Reasons why a path of the extracted modules can't be re-rooted.
+#[derive(Debug, PartialEq)]
+enum RerootError {
+    SuperOutsideOfExtractedModules,
+    UnsupportedImport,
+}
+
+impl RerootError {
+    fn into_rewrite_error(self, file: &Path, ancestors: &[String]) -> RewriteError {
+        let file = file.to_owned();
+        let module = to_module_name(&[&["crate".to_owned()], ancestors].concat());
+        match self {
+            RerootError::SuperOutsideOfExtractedModules => {
+                RewriteError::SuperOutsideOfExtractedModules { file, module }
+            }
+            RerootError::UnsupportedImport => RewriteError::UnsupportedImport { file, module },
+        }
+    }
+}
+
+fn to_module_name(module: &[String]) -> ModuleName {
+    ModuleName::from(module.join("::"))
+}
+
+/// The segments of `module` after `crate`.
+pub(crate) fn ancestors_of(module: &ModuleName) -> Vec<String> {
+    module
+        .as_str()
+        .split("::")
+        .skip(1)
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Follows `self` and `super` segments from `module`, or returns `None` if they go above the
+/// crate root.
+fn follow_keywords(module: &[String], keywords: &[Segment]) -> Option<Vec<String>> {
+    let mut module = module.to_vec();
+    for (keyword, _) in keywords {
+        if keyword == "super" {
+            if module.len() == 1 {
+                return None;
+            }
+            module.pop();
+        }
+    }
+    Some(module)
+}
+
+impl Context<'_> {
+    /// The path of `module` in the new crate, if it is extracted.
+    fn new_path(&self, module: &[String]) -> Option<Vec<String>> {
+        let new_module = self.mapping.map(&to_module_name(module))?;
+        Some(new_module.as_str().split("::").map(str::to_owned).collect())
+    }
+
+    /// Re-roots the leading segments of a path written in the extracted module `ancestors`.
+    /// `prefix` is the absolute module path the segments are relative to, inside a `use` group.
+    ///
+    /// Returns `None` if the path doesn't need to change: it points to another crate, is
+    /// relative to a submodule, or keeps the same meaning in the new crate.
+    fn reroot(
+        &self,
+        ancestors: &[String],
+        prefix: Option<&[String]>,
+        segments: &[Segment],
+    ) -> Result<Option<Rerooted>, RerootError> {
+        let Some((first, _)) = segments.first() else {
+            return Ok(None);
+        };
+        let mut source = vec!["crate".to_owned()];
+        source.extend_from_slice(ancestors);
+        let keywords = if prefix.is_none() {
+            segments
+                .iter()
+                .take_while(|(segment, _)| segment == "self" || segment == "super")
+                .count()
+        } else {
+            0
+        };
+        let (mut module, mut consumed) = match prefix {
+            Some(prefix) => (prefix.to_vec(), 0),
+            None if keywords > 0 => match follow_keywords(&source, &segments[..keywords]) {
+                Some(target) => (target, keywords),
+                None => return Err(RerootError::SuperOutsideOfExtractedModules),
+            },
+            None if first == "crate" => (vec!["crate".to_owned()], 1),
+            None => return Ok(None),
+        };
+        while self.new_path(&module).is_none() && consumed < segments.len() {
+            let mut submodule = module.clone();
+            submodule.push(segments[consumed].0.clone());
+            if !self.modules.contains_key(&to_module_name(&submodule)) {
+                break;
+            }
+            module = submodule;
+            consumed += 1;
+        }
+        let Some(new_module) = self.new_path(&module) else {
+            if segments[..keywords]
+                .iter()
+                .any(|(keyword, _)| keyword == "super")
+            {
+                return Err(RerootError::SuperOutsideOfExtractedModules);
+            }
+            return Ok(None);
+        };
+        if consumed == 0 {
+            return Ok(None);
+        }
+        if keywords > 0 {
+            // A relative path is kept if it still points to the same module.
+            let new_source = self
+                .new_path(&source)
+                .expect("paths are re-rooted in extracted modules");
+            let relative = follow_keywords(&new_source, &segments[..keywords]).map(|mut target| {
+                target.extend(
+                    segments[keywords..consumed]
+                        .iter()
+                        .map(|(segment, _)| segment.clone()),
+                );
+                target
+            });
+            if relative.as_ref() == Some(&new_module) {
+                return Ok(None);
+            }
+        }
+        let text = match prefix {
+            None => new_module.join("::"),
+            Some(prefix) => match new_module.strip_prefix(prefix) {
+                Some(suffix) => suffix.join("::"),
+                None => return Err(RerootError::UnsupportedImport),
+            },
+        };
+        let original = segments[..consumed]
+            .iter()
+            .map(|(segment, _)| segment.as_str())
+            .collect::<Vec<_>>()
+            .join("::");
+        if text == original {
+            return Ok(None);
+        }
+        Ok(Some(Rerooted {
+            consumed,
+            module: new_module,
+            text,
+        }))
+    }
+
+    /// Re-roots the paths of a `use` tree written in the extracted module `ancestors`, adding
+    /// the resulting edits to `edits`.
+    fn reroot_use_tree(
+        &self,
+        tree: &UseTree,
+        ancestors: &[String],
+        prefix: Option<&[String]>,
+        edits: &mut Vec<TextEdit>,
+    ) -> Result<(), RerootError> {
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut terminal = tree;
+        while let UseTree::Path(UsePath { ident, tree, .. }) = terminal {
+            segments.push((ident.to_string(), ident.span().byte_range()));
+            terminal = tree;
+        }
+        let path_len = segments.len();
+        match terminal {
+            UseTree::Name(UseName { ident }) | UseTree::Rename(UseRename { ident, .. })
+                if ident != "self" =>
+            {
+                segments.push((ident.to_string(), ident.span().byte_range()));
+            }
+            _ => {}
+        }
+
+        let Some(rerooted) = self.reroot(ancestors, prefix, &segments)? else {
+            // The group may still contain paths to re-root, relative to its prefix.
+            if let UseTree::Group(UseGroup { items, .. }) = terminal
+                && let Some(group_prefix) = absolute_prefix(prefix, &segments[..path_len])
+                && self.new_path(&group_prefix).is_none()
+            {
+                for item in items {
+                    self.reroot_use_tree(item, ancestors, Some(&group_prefix), edits)?;
+                }
+            }
+            return Ok(());
+        };
+
+        let start = segments[0].1.start;
+        let mut end = segments[rerooted.consumed - 1].1.end;
+        let mut text = rerooted.text;
+        if rerooted.consumed > path_len {
+            // The module itself is imported, which needs a name if it becomes the crate root.
+            if text.is_empty() {
+                text = "self".to_owned();
+            }
+            if let UseTree::Name(UseName { ident }) = terminal
+                && (text == "crate"
+                    || text == "self"
+                    || text.rsplit("::").next() != Some(&ident.to_string()))
+            {
+                text = format!("{text} as {ident}");
+            }
+        } else if text.is_empty() {
+            // Remove the separator along with the segments.
+            end = segments
+                .get(rerooted.consumed)
+                .map_or(terminal.span().byte_range().start, |(_, range)| range.start);
+        }
+        edits.push(TextEdit {
+            range: start..end,
+            text,
+        });
+
+        if rerooted.consumed == path_len
+            && rerooted.module.len() == 1
+            && let UseTree::Group(UseGroup { items, .. }) = terminal
+        {
+            let name = &segments[path_len - 1].0;
+            for item in items {
+                if let UseTree::Name(UseName { ident }) = item
+                    && ident == "self"
+                {
+                    edits.push(TextEdit {
+                        range: ident.span().byte_range(),
+                        text: format!("self as {name}"),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The absolute module path of a `use` group, given the segments before it.
+fn absolute_prefix(prefix: Option<&[String]>, segments: &[Segment]) -> Option<Vec<String>> {
+    let (mut module, segments) = match (prefix, segments.split_first()) {
+        (Some(prefix), _) => (prefix.to_vec(), segments),
+        (None, Some(((first, _), rest))) if first == "crate" => (vec![first.clone()], rest),
+        _ => return None,
+    };
+    module.extend(segments.iter().map(|(segment, _)| segment.clone()));
+    Some(module)
+}
+
+/// Finds the paths to re-root in the extracted modules outside of `use` statements.
+struct ExtractedPathVisitor<'a> {
+    context: &'a Context<'a>,
+    ancestors: Vec<String>,
+    edits: Vec<TextEdit>,
+    error: Option<(RerootError, Vec<String>)>,
+}
+
+impl<'ast> Visit<'ast> for ExtractedPathVisitor<'_> {
+    fn visit_item_mod(&mut self, node: &'ast ItemMod) {
+        self.ancestors.push(node.ident.to_string());
+        visit::visit_item_mod(self, node);
+        self.ancestors.pop();
+    }
+
+    fn visit_item_use(&mut self, _: &'ast ItemUse) {
+        // Re-rooted from the spans of the use statement map.
+    }
+
+    fn visit_path(&mut self, node: &'ast syn::Path) {
+        if node.leading_colon.is_none() && self.error.is_none() {
+            // The last segment is an item rather than a module.
+            let segments = node
+                .segments
+                .iter()
+                .take(node.segments.len() - 1)
+                .take_while(|segment| segment.arguments.is_none())
+                .map(|segment| (segment.ident.to_string(), segment.ident.span().byte_range()))
+                .collect::<Vec<_>>();
+            match self.context.reroot(&self.ancestors, None, &segments) {
+                Ok(Some(rerooted)) => self.edits.push(TextEdit {
+                    range: segments[0].1.start..segments[rerooted.consumed - 1].1.end,
+                    text: rerooted.text,
+                }),
+                Ok(None) => {}
+                Err(error) => self.error = Some((error, self.ancestors.clone())),
+            }
+        }
+        visit::visit_path(self, node);
+    }
+}
+
+/// Finds the paths to rewrite in the tokens of macros, which can't be rewritten since the
+/// tokens aren't parsed as Rust code: paths to the extracted modules in the parent crate, and
+/// paths to re-root in the extracted modules.
+struct MacroPathVisitor<'a> {
+    context: &'a Context<'a>,
+    file: &'a Path,
+    content: &'a str,
+    ancestors: Vec<String>,
+    warnings: Vec<String>,
+}
+
+impl<'ast> Visit<'ast> for MacroPathVisitor<'_> {
+    fn visit_item_mod(&mut self, node: &'ast ItemMod) {
+        self.ancestors.push(node.ident.to_string());
+        visit::visit_item_mod(self, node);
+        self.ancestors.pop();
+    }
+
+    fn visit_macro(&mut self, node: &'ast Macro) {
+        let module = to_module_name(&[&["crate".to_owned()], self.ancestors.as_slice()].concat());
+        let extracted = self.context.is_extracted(&module);
+        let mut paths = Vec::new();
+        macro_paths(node.tokens.clone(), &mut paths);
+        for path in paths {
+            let edits = if extracted {
+                let mut visitor = ExtractedPathVisitor {
+                    context: self.context,
+                    ancestors: self.ancestors.clone(),
+                    edits: Vec::new(),
+                    error: None,
+                };
+                visitor.visit_path(&path);
+                if visitor.error.is_some() {
+                    self.warn(&path, "points outside of the extracted modules".to_owned());
+                    continue;
+                }
+                if let Some(target) = self.context.resolve_module(&self.ancestors, &path)
+                    && !self.context.is_extracted(&target)
+                {
+                    self.warn(
+                        &path,
+                        format!("points to {target}, which stays in the parent crate"),
+                    );
+                    continue;
+                }
+                visitor.edits
+            } else {
+                let mut visitor = PathVisitor {
+                    context: self.context,
+                    ancestors: self.ancestors.clone(),
+                    edits: Vec::new(),
+                };
+                visitor.rewrite_path(&path);
+                visitor.edits
+            };
+            if !edits.is_empty() {
+                let range = path_range(&path);
+                let rewritten = splice(&self.content[range.clone()], range.start, &edits);
+                self.warn(
+                    &path,
+                    format!("is not rewritten: change it to `{rewritten}`"),
+                );
+            }
+        }
+    }
+}
+
+impl MacroPathVisitor<'_> {
+    fn warn(&mut self, path: &syn::Path, problem: String) {
+        let line = path.span().start().line;
+        self.warnings.push(format!(
+            "`{}` in a macro in {}, line {line}, {problem}",
+            &self.content[path_range(path)],
+            self.file.display(),
+        ));
+    }
+}
+
+/// The byte range of a path parsed from the tokens of a macro, from its first segment.
+fn path_range(path: &syn::Path) -> Range<usize> {
+    let start = path.segments.first().map(|segment| segment.ident.span());
+    let end = path.segments.last().map(|segment| segment.ident.span());
+    match (start, end) {
+        (Some(start), Some(end)) => start.byte_range().start..end.byte_range().end,
+        _ => 0..0,
+    }
+}
+
+/// Collects the paths of more than one segment in the tokens of a macro, such as
+/// `crate::auth::Token`, except those with a leading `::` and those starting with a
+/// `macro_rules!` metavariable.
+fn macro_paths(tokens: TokenStream, paths: &mut Vec<syn::Path>) {
+    let tokens = tokens.into_iter().collect::<Vec<_>>();
+    let is_punct = |token: &TokenTree, char: char| matches!(token, TokenTree::Punct(punct) if punct.as_char() == char);
+    let mut index = 0;
+    while index < tokens.len() {
+        let start = index;
+        index += 1;
+        match &tokens[start] {
+            TokenTree::Group(group) => macro_paths(group.stream(), paths),
+            TokenTree::Ident(ident) => {
+                while let Some([TokenTree::Punct(colon), second_colon, TokenTree::Ident(_)]) =
+                    tokens.get(index..index + 3)
+                    && colon.as_char() == ':'
+                    && colon.spacing() == Spacing::Joint
+                    && is_punct(second_colon, ':')
+                {
+                    index += 3;
+                }
+                let previous = start.checked_sub(1).map(|previous| &tokens[previous]);
+                let skipped = previous.is_some_and(|previous| {
+                    is_punct(previous, ':') || (is_punct(previous, '$') && ident != "crate")
+                });
+                if index - start > 1
+                    && !skipped
+                    && let Ok(path) = syn::parse2(tokens[start..index].iter().cloned().collect())
+                {
+                    paths.push(path);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The indentation of the line containing `offset`, if it only has whitespace before it.
+fn indent_at(content: &str, offset: usize) -> &str {
+    let line_start = content[..offset].rfind('\n').map_or(0, |index| index + 1);
+    let indent = &content[line_start..offset];
+    if indent.chars().all(char::is_whitespace) {
+        indent
+    } else {
+        ""
+    }
+}
+
+/// Rewrites the paths to the extracted modules in the rest of the crate, so that they point to
+/// the new crate instead: `use crate::auth::Token` becomes `use my_crate_auth::Token`.
+///
+/// `use` statements are edited in place from the spans of the use statement map, splitting
+/// groups that import from both the extracted modules and the rest of the crate. Other
+/// fully-qualified paths only have their module prefix replaced.
+pub fn rewrite_parent_imports(
+    crate_root: &Path,
+    crate_modules: &CrateModules,
+    mapping: &ModuleMapping,
+    crate_name: &str,
+) -> Result<Vec<SourceChange>, RewriteError> {
+    let context = Context {
+        mapping,
+        crate_name: crate_name.replace('-', "_"),
+        modules: &crate_modules.modules,
+    };
+    let mut changes = Vec::new();
+    for (module, info) in &crate_modules.modules {
+        if info.inline || context.is_extracted(module) {
+            continue;
+        }
+        let path = crate_root.join(&info.file);
+        let content =
+            read_to_string(&path).map_err(|_| RewriteError::FileNotReadable(info.file.clone()))?;
+        let mut edits = Vec::new();
+
+        for use_statement in file_use_statements(crate_modules, &info.file) {
+            if context.is_extracted(use_statement.source_module()) {
+                continue;
+            }
+            let range = use_statement.span().byte_range();
+            let ancestors = ancestors_of(use_statement.source_module());
+            if let Some(text) = context.rewrite_use_item(
+                &content[range.clone()],
+                &ancestors,
+                indent_at(&content, range.start),
+            ) {
+                edits.push(TextEdit { range, text });
+            }
+        }
+
+        let parsed_file = syn::parse_file(&content)
+            .map_err(|_| RewriteError::FileNotParsable(info.file.clone()))?;
+        let mut visitor = PathVisitor {
+            context: &context,
+            ancestors: ancestors_of(module),
+            edits: Vec::new(),
+        };
+        visitor.visit_file(&parsed_file);
+        edits.extend(visitor.edits);
+
+        if !edits.is_empty() {
+            edits.sort_by_key(|edit| edit.range.start);
+            changes.push(SourceChange {
+                file: info.file.clone(),
+                edits,
+            });
+        }
+    }
+    Ok(changes)
+}
+
+/// Re-roots the paths inside the extracted modules on the new crate: with `crate::auth`
+/// extracted as a crate, `crate::auth::jwt::Claims` becomes `crate::jwt::Claims`.
+///
+/// Relative paths are kept when they still point to the same module, and re-rooted otherwise.
+/// A `super` going out of the extracted modules into the parent crate can't be re-rooted and
+/// fails the rewrite.
+pub fn reroot_extracted_paths(
+    crate_root: &Path,
+    crate_modules: &CrateModules,
+    mapping: &ModuleMapping,
+) -> Result<Vec<SourceChange>, RewriteError> {
+    let context = Context {
+        mapping,
+        crate_name: String::new(),
+        modules: &crate_modules.modules,
+    };
+    let mut changes = Vec::new();
+    for (module, info) in &crate_modules.modules {
+        if info.inline || !context.is_extracted(module) {
+            continue;
+        }
+        let path = crate_root.join(&info.file);
+        let content =
+            read_to_string(&path).map_err(|_| RewriteError::FileNotReadable(info.file.clone()))?;
+        let mut edits = context.reroot_use_statements(crate_modules, &info.file, &content)?;
+
+        let parsed_file = syn::parse_file(&content)
+            .map_err(|_| RewriteError::FileNotParsable(info.file.clone()))?;
+        let mut visitor = ExtractedPathVisitor {
+            context: &context,
+            ancestors: ancestors_of(module),
+            edits: Vec::new(),
+            error: None,
+        };
+        visitor.visit_file(&parsed_file);
+        if let Some((error, ancestors)) = visitor.error {
+            return Err(error.into_rewrite_error(&info.file, &ancestors));
+        }
+        edits.extend(visitor.edits);
+
+        if !edits.is_empty() {
+            edits.sort_by_key(|edit| edit.range.start);
+            changes.push(SourceChange {
+                file: info.file.clone(),
+                edits,
+            });
+        }
+    }
+    Ok(changes)
+}
+
+impl Context<'_> {
+    /// Edits re-rooting the `use` statements of the extracted modules in `file`.
+    fn reroot_use_statements(
+        &self,
+        crate_modules: &CrateModules,
+        file: &Path,
+        content: &str,
+    ) -> Result<Vec<TextEdit>, RewriteError> {
+        let mut edits = Vec::new();
+        for use_statement in file_use_statements(crate_modules, file) {
+            if !self.is_extracted(use_statement.source_module()) {
+                continue;
+            }
+            let range = use_statement.span().byte_range();
+            let ancestors = ancestors_of(use_statement.source_module());
+            let Ok(item) = syn::parse_str::<ItemUse>(&content[range.clone()]) else {
+                continue;
+            };
+            if item.leading_colon.is_some() {
+                continue;
+            }
+            let mut use_edits = Vec::new();
+            self.reroot_use_tree(&item.tree, &ancestors, None, &mut use_edits)
+                .map_err(|error| error.into_rewrite_error(file, &ancestors))?;
+            edits.extend(use_edits.into_iter().map(|edit| TextEdit {
+                range: edit.range.start + range.start..edit.range.end + range.start,
+                text: edit.text,
+            }));
+        }
+        Ok(edits)
+    }
+}
+
+/// Extends `range` to the whole lines it spans, if it is alone on them, so that deleting it
+/// leaves no empty line behind. A blank line following it is included too if it would be left
+/// at the start of the file or after another blank line.
+fn whole_lines(content: &str, range: Range<usize>) -> Range<usize> {
+    let start = range.start - indent_at(content, range.start).len();
+    let rest = &content[range.end..];
+    let mut end = match rest.find('\n') {
+        Some(index) if rest[..index].trim().is_empty() => range.end + index + 1,
+        None if rest.trim().is_empty() => content.len(),
+        _ => return range,
+    };
+    let before = content[..start].trim_end_matches([' ', '\t']);
+    if (before.is_empty() || before.ends_with("\n\n"))
+        && let Some(index) = content[end..].find('\n')
+        && content[end..end + index].trim().is_empty()
+    {
+        end += index + 1;
+    }
+    start..end
+}
+
+/// Attributes of a module declaration that keep their meaning as inner attributes of the module.
+const KEPT_ATTRIBUTES: [&str; 7] = ["doc", "allow", "warn", "deny", "forbid", "expect", "cfg"];
+
+/// The documentation, lint and `cfg` attributes of a module declaration, as inner attributes of
+/// the module itself: `/// Auth.` becomes `//! Auth.`, `#[allow(dead_code)]`
+/// `#![allow(dead_code)]`.
+fn inner_attributes(content: &str, attrs: &[Attribute]) -> String {
+    let mut inner = String::new();
+    for attr in attrs {
+        if !is_kept(attr) {
+            continue;
+        }
+        let text = &content[attr.span().byte_range()];
+        let converted = if let Some(rest) = text.strip_prefix("///") {
+            format!("//!{rest}")
+        } else if let Some(rest) = text.strip_prefix("/**") {
+            format!("/*!{rest}")
+        } else if let Some(rest) = text.strip_prefix('#') {
+            format!("#!{rest}")
+        } else {
+            continue;
+        };
+        inner.push_str(&converted);
+        inner.push('\n');
+    }
+    inner
+}
+
+fn is_kept(attr: &Attribute) -> bool {
+    KEPT_ATTRIBUTES
+        .iter()
+        .any(|name| attr.path().is_ident(name))
+}
+
+/// The declaration of the module `name` among `items`, inline or not.
+fn find_module<'a>(items: &'a [Item], name: &str, inline: bool) -> Option<&'a ItemMod> {
+    items.iter().find_map(|item| match item {
+        Item::Mod(item) if item.ident == name && item.content.is_some() == inline => Some(item),
+        _ => None,
+    })
+}
+
+/// The declaration of a module in the file of its parent, along with that file and its content.
+struct Declaration {
+    file: PathBuf,
+    content: String,
+    item: ItemMod,
+}
+
+/// Finds the declaration of `module`, inline or not, in the file of its parent.
+fn find_declaration(
+    crate_root: &Path,
+    modules: &ModuleTree,
+    module: &ModuleName,
+) -> Result<Option<Declaration>, RewriteError> {
+    let (Some(parent), Some(info)) = (module.parent(), modules.get(module)) else {
+        return Ok(None);
+    };
+    // The parent may be declared inline in the file of one of its ancestors.
+    let mut file_module = parent;
+    let mut inline_path = Vec::new();
+    while let Some(parent_info) = modules.get(&file_module)
+        && parent_info.inline
+        && let Some(grandparent) = file_module.parent()
+    {
+        inline_path.push(ancestors_of(&file_module).pop().unwrap_or_default());
+        file_module = grandparent;
+    }
+    let Some(file_info) = modules.get(&file_module) else {
+        return Ok(None);
+    };
+    let file = file_info.file.clone();
+    let content = read_to_string(crate_root.join(&file))
+        .map_err(|_| RewriteError::FileNotReadable(file.clone()))?;
+    let parsed_file =
+        syn::parse_file(&content).map_err(|_| RewriteError::FileNotParsable(file.clone()))?;
+
+    let mut items = parsed_file.items.as_slice();
+    for name in inline_path.iter().rev() {
+        let Some((_, inline_items)) =
+            find_module(items, name, true).and_then(|item| item.content.as_ref())
+        else {
+            return Ok(None);
+        };
+        items = inline_items;
+    }
+    let name = ancestors_of(module).pop().unwrap_or_default();
+    let item = find_module(items, &name, info.inline).cloned();
+    Ok(item.map(|item| Declaration {
+        file,
+        content,
+        item,
+    }))
+}
+
+/// Removes the `mod` declarations of the extracted subtrees from their parent modules, along
+/// with the body of the inline ones.
+///
+/// The documentation, lint and `cfg` attributes of the declarations are moved to the top of the
+/// files of the modules, as inner attributes, so that a module that becomes the root of the new
+/// crate documents it and a gated module gates the whole crate.
+pub fn remove_module_declarations(
+    crate_root: &Path,
+    crate_modules: &CrateModules,
+    mapping: &ModuleMapping,
+) -> Result<Vec<SourceChange>, RewriteError> {
+    let mut changes = Vec::new();
+    for (root, _) in mapping.roots() {
+        let Some(declaration) = find_declaration(crate_root, &crate_modules.modules, root)? else {
+            continue;
+        };
+        let mut change = SourceChange::new(declaration.file.clone());
+        change.push(TextEdit::delete(whole_lines(
+            &declaration.content,
+            declaration.item.span().byte_range(),
+        )));
+        changes.push(change);
+
+        let info = &crate_modules.modules[root];
+        if !info.inline {
+            let inner = inner_attributes(&declaration.content, &declaration.item.attrs);
+            if !inner.is_empty() {
+                let mut change = SourceChange::new(info.file.clone());
+                change.push(TextEdit::insert(0, inner));
+                changes.push(change);
+            }
+        }
+    }
+    Ok(changes)
+}
+
+/// Warnings about the attributes of the declarations of the extracted subtrees that don't move
+/// along with them, such as `#[macro_use]`.
+pub fn dropped_declaration_attributes(
+    crate_root: &Path,
+    crate_modules: &CrateModules,
+    mapping: &ModuleMapping,
+) -> Result<Vec<String>, RewriteError> {
+    let mut warnings = Vec::new();
+    for (root, _) in mapping.roots() {
+        let Some(declaration) = find_declaration(crate_root, &crate_modules.modules, root)? else {
+            continue;
+        };
+        for attr in declaration.item.attrs.iter().filter(|attr| !is_kept(attr)) {
+            warnings.push(format!(
+                "`{}` on the declaration of {root} in {} is dropped",
+                &declaration.content[attr.span().byte_range()],
+                declaration.file.display()
+            ));
+        }
+    }
+    Ok(warnings)
+}
+
+/// Fails if a module of the extracted subtrees is declared with `#[path]`: the modules are
+/// looked up at their default location, so the files moved wouldn't be the right ones.
+pub fn check_declaration_paths(
+    crate_root: &Path,
+    crate_modules: &CrateModules,
+    mapping: &ModuleMapping,
+) -> Result<(), RewriteError> {
+    for (root, _) in mapping.roots() {
+        for module in crate_modules
+            .modules
+            .keys()
+            .filter(|module| module.is_descendant_of(root))
+        {
+            let Some(declaration) = find_declaration(crate_root, &crate_modules.modules, module)?
+            else {
+                continue;
+            };
+            if declaration
+                .item
+                .attrs
+                .iter()
+                .any(|attr| attr.path().is_ident("path"))
+            {
+                return Err(RewriteError::PathAttribute {
+                    file: declaration.file,
+                    module: module.clone(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Warnings about the paths inside macro invocations and `macro_rules!` definitions that are
+/// not rewritten: paths to the extracted modules in the parent crate, and paths in the
+/// extracted modules that don't point to the same module once moved.
+pub fn macro_path_warnings(
+    crate_root: &Path,
+    crate_modules: &CrateModules,
+    mapping: &ModuleMapping,
+    crate_name: &str,
+) -> Result<Vec<String>, RewriteError> {
+    let context = Context {
+        mapping,
+        crate_name: crate_name.replace('-', "_"),
+        modules: &crate_modules.modules,
+    };
+    let mut warnings = Vec::new();
+    for (module, info) in &crate_modules.modules {
+        if info.inline {
+            continue;
+        }
+        let content = read_to_string(crate_root.join(&info.file))
+            .map_err(|_| RewriteError::FileNotReadable(info.file.clone()))?;
+        let parsed_file = syn::parse_file(&content)
+            .map_err(|_| RewriteError::FileNotParsable(info.file.clone()))?;
+        let mut visitor = MacroPathVisitor {
+            context: &context,
+            file: &info.file,
+            content: &content,
+            ancestors: ancestors_of(module),
+            warnings: Vec::new(),
+        };
+        visitor.visit_file(&parsed_file);
+        warnings.extend(visitor.warnings);
+    }
+    Ok(warnings)
+}
+
+/// Removes the indentation common to the non-blank lines of `text`.
+fn dedent(text: &str) -> String {
+    let indent = text
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or_default();
+    let mut dedented = String::with_capacity(text.len());
+    for line in text.lines() {
+        if !line.trim().is_empty() {
+            dedented.push_str(&line[indent..]);
+        }
+        dedented.push('\n');
+    }
+    dedented
+}
+
+/// An extracted inline module, lifted out of the file declaring it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiftedModule {
+    /// The path of the module in the new crate.
+    pub new_module: ModuleName,
+    /// The content of its file in the new crate.
+    pub content: String,
+}
+
+/// Lifts the extracted inline modules out of the files declaring them, to be written to their
+/// own file in the new crate: their body with its paths re-rooted and de-indented, after their
+/// documentation and lint attributes turned into inner attributes.
+///
+/// Their out-of-line submodules are moved along with the other extracted files.
+pub fn lift_inline_modules(
+    crate_root: &Path,
+    crate_modules: &CrateModules,
+    mapping: &ModuleMapping,
+) -> Result<Vec<LiftedModule>, RewriteError> {
+    let context = Context {
+        mapping,
+        crate_name: String::new(),
+        modules: &crate_modules.modules,
+    };
+    let mut lifted = Vec::new();
+    for (root, new_root) in mapping.roots() {
+        if !crate_modules
+            .modules
+            .get(root)
+            .is_some_and(|info| info.inline)
+        {
+            continue;
+        }
+        let Some(Declaration {
+            file,
+            content,
+            item,
+        }) = find_declaration(crate_root, &crate_modules.modules, root)?
+        else {
+            continue;
+        };
+        let Some((brace, _)) = &item.content else {
+            continue;
+        };
+        let body = brace.span.open().byte_range().end..brace.span.close().byte_range().start;
+
+        let mut edits = context.reroot_use_statements(crate_modules, &file, &content)?;
+        let mut visitor = ExtractedPathVisitor {
+            context: &context,
+            ancestors: root.parent().as_ref().map(ancestors_of).unwrap_or_default(),
+            edits: Vec::new(),
+            error: None,
+        };
+        visitor.visit_item_mod(&item);
+        if let Some((error, ancestors)) = visitor.error {
+            return Err(error.into_rewrite_error(&file, &ancestors));
+        }
+        edits.extend(visitor.edits);
+        edits.retain(|edit| body.start <= edit.range.start && edit.range.end <= body.end);
+
+        let text = splice(&content[body.clone()], body.start, &edits);
+        let text = text.trim_start_matches(['\r', '\n']).trim_end();
+        let mut module_content = inner_attributes(&content, &item.attrs);
+        if !text.is_empty() {
+            if !module_content.is_empty() {
+                module_content.push('\n');
+            }
+            module_content.push_str(&dedent(text));
+        }
+        lifted.push(LiftedModule {
+            new_module: new_root.clone(),
+            content: module_content,
+        });
+    }
+    Ok(lifted)
+}
+
+/// The use statements of a file of the crate.
+fn file_use_statements<'a>(crate_modules: &'a CrateModules, file: &Path) -> &'a [UseStatement] {
+    crate_modules
+        .use_statements
+        .iter()
+        .find(|(use_file, _)| use_file.path() == file)
+        .map(|(_, use_statements)| use_statements.as_slice())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::read_to_string;
+    use std::path::{Path, PathBuf};
+
+    use pretty_assertions::assert_eq;
+
+    use crate::dependencies::{ItemCounts, ModuleInfo, ModuleName, ModuleTree, list_modules};
+    use crate::refactor::ModuleMapping;
+
+    use syn::ItemUse;
+    use syn::visit::Visit;
+
+    use super::{
+        Context, LiftedModule, MacroPathVisitor, PathVisitor, RerootError, RewriteError, dedent,
+        dropped_declaration_attributes, inner_attributes, lift_inline_modules,
+        remove_module_declarations, reroot_extracted_paths, rewrite_parent_imports, splice,
+        whole_lines,
+    };
+
+    fn test_modules() -> ModuleTree {
+        [
+            "crate",
+            "crate::auth",
+            "crate::auth::jwt",
+            "crate::db",
+            "crate::services",
+            "crate::services::session",
+        ]
+        .into_iter()
+        .map(|module| {
+            (
+                ModuleName::from(module),
+                ModuleInfo {
+                    file: PathBuf::new(),
+                    inline: false,
+                    lines: 0,
+                    items: ItemCounts::default(),
+                },
+            )
+        })
+        .collect()
+    }
+
+    fn test_mapping(extracted: &[&str], modules: &ModuleTree) -> ModuleMapping {
+        let extracted = extracted
+            .iter()
+            .map(|module| ModuleName::from(*module))
+            .collect::<Vec<_>>();
+        ModuleMapping::new(&extracted, &extracted, modules).unwrap()
+    }
+
+    fn to_ancestors(ancestors: &[&str]) -> Vec<String> {
+        ancestors
+            .iter()
+            .map(|ancestor| ancestor.to_string())
+            .collect()
+    }
+
+    fn rewrite(source: &str, extracted: &[&str], ancestors: &[&str]) -> Option<String> {
+        let modules = test_modules();
+        let mapping = test_mapping(extracted, &modules);
+        let context = Context {
+            mapping: &mapping,
+            crate_name: "my_crate_auth".to_owned(),
+            modules: &modules,
+        };
+        context.rewrite_use_item(source, &to_ancestors(ancestors), "    ")
+    }
+
+    fn rewrite_paths(source: &str, extracted: &[&str]) -> String {
+        let modules = test_modules();
+        let mapping = test_mapping(extracted, &modules);
+        let context = Context {
+            mapping: &mapping,
+            crate_name: "my_crate_auth".to_owned(),
+            modules: &modules,
+        };
+        let mut visitor = PathVisitor {
+            context: &context,
+            ancestors: Vec::new(),
+            edits: Vec::new(),
+        };
+        visitor.visit_file(&syn::parse_file(source).unwrap());
+        splice(source, 0, &visitor.edits)
+    }
+
+    fn macro_warnings(source: &str, extracted: &[&str], ancestors: &[&str]) -> Vec<String> {
+        let modules = test_modules();
+        let mapping = test_mapping(extracted, &modules);
+        let context = Context {
+            mapping: &mapping,
+            crate_name: "my_crate_auth".to_owned(),
+            modules: &modules,
+        };
+        let mut visitor = MacroPathVisitor {
+            context: &context,
+            file: Path::new("src/lib.rs"),
+            content: source,
+            ancestors: to_ancestors(ancestors),
+            warnings: Vec::new(),
+        };
+        visitor.visit_file(&syn::parse_file(source).unwrap());
+        visitor.warnings
+    }
+
+    fn reroot(source: &str, extracted: &[&str], ancestors: &[&str]) -> Result<String, RerootError> {
+        let modules = test_modules();
+        let mapping = test_mapping(extracted, &modules);
+        let context = Context {
+            mapping: &mapping,
+            crate_name: String::new(),
+            modules: &modules,
+        };
+        let item = syn::parse_str::<ItemUse>(source).unwrap();
+        let mut edits = Vec::new();
+        context.reroot_use_tree(&item.tree, &to_ancestors(ancestors), None, &mut edits)?;
+        Ok(splice(source, 0, &edits))
+    }
+
+    #[test]
+    fn rewrites_simple_import() {
+        assert_eq!(
+            rewrite("use crate::auth::Token;", &["crate::auth"], &[]),
+            Some("use my_crate_auth::Token;".to_owned())
+        );
+        assert_eq!(
+            rewrite(
+                "pub(crate) use crate::auth::jwt::{Claims, decode};",
+                &["crate::auth"],
+                &[]
+            ),
+            Some("pub(crate) use my_crate_auth::jwt::{Claims, decode};".to_owned())
+        );
+        assert_eq!(rewrite("use crate::db::Pool;", &["crate::auth"], &[]), None);
+        assert_eq!(rewrite("use std::fmt;", &["crate::auth"], &[]), None);
+    }
+
+    #[test]
+    fn rewrites_module_import() {
+        assert_eq!(
+            rewrite("use crate::auth;", &["crate::auth"], &[]),
+            Some("use my_crate_auth as auth;".to_owned())
+        );
+        assert_eq!(
+            rewrite("use crate::auth as authentication;", &["crate::auth"], &[]),
+            Some("use my_crate_auth as authentication;".to_owned())
+        );
+        assert_eq!(
+            rewrite("use crate::auth::{self, Token};", &["crate::auth"], &[]),
+            Some("use my_crate_auth::{self as auth, Token};".to_owned())
+        );
+    }
+
+    #[test]
+    fn rewrites_relative_imports() {
+        assert_eq!(
+            rewrite("use super::auth::Token;", &["crate::auth"], &["db"]),
+            Some("use my_crate_auth::Token;".to_owned())
+        );
+        assert_eq!(
+            rewrite(
+                "use super::super::auth::Token;",
+                &["crate::auth"],
+                &["services", "session"]
+            ),
+            Some("use my_crate_auth::Token;".to_owned())
+        );
+        assert_eq!(
+            rewrite("use auth::Token;", &["crate::auth"], &[]),
+            Some("use my_crate_auth::Token;".to_owned())
+        );
+        assert_eq!(rewrite("use auth::Token;", &["crate::auth"], &["db"]), None);
+    }
+
+    #[test]
+    fn splits_groups() {
+        assert_eq!(
+            rewrite("use crate::{auth::Token, db};", &["crate::auth"], &[]),
+            Some("use crate::db;\n    use my_crate_auth::Token;".to_owned())
+        );
+        assert_eq!(
+            rewrite(
+                "use crate::{db::{self, Pool}, auth::{Token, jwt}, services};",
+                &["crate::auth"],
+                &[]
+            ),
+            Some(
+                "use crate::{db::{self, Pool}, services};\n    use my_crate_auth::{Token, jwt};"
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            rewrite(
+                "use crate::{\n    db,\n    auth::Token,\n    auth::jwt::Claims,\n};",
+                &["crate::auth"],
+                &[]
+            ),
+            Some(
+                "use crate::db;\n    use my_crate_auth::Token;\n    use my_crate_auth::jwt::Claims;"
+                    .to_owned()
+            )
+        );
+        assert_eq!(
+            rewrite(
+                "use crate::{\n    auth::Token,\n    db,\n    services,\n};",
+                &["crate::auth"],
+                &[]
+            ),
+            Some(
+                "use crate::{\n    db,\n    services,\n};\n    use my_crate_auth::Token;"
+                    .to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn rewrites_to_nested_module_of_new_crate() {
+        assert_eq!(
+            rewrite(
+                "use crate::services::{session::Session, self};",
+                &["crate::auth", "crate::services::session"],
+                &[]
+            ),
+            Some(
+                "use crate::services::{self};\n    use my_crate_auth::session::Session;".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn rewrites_paths_but_not_items_named_like_the_module() {
+        assert_eq!(
+            rewrite_paths(
+                "fn auth() -> u32 { let auth = 3; auth + auth::jwt::VALUE + crate::auth::LEN }",
+                &["crate::auth"]
+            ),
+            "fn auth() -> u32 { let auth = 3; auth + my_crate_auth::jwt::VALUE + my_crate_auth::LEN }"
+        );
+        assert_eq!(
+            rewrite_paths("fn main() { auth(); }", &["crate::auth"]),
+            "fn main() { auth(); }"
+        );
+    }
+
+    #[test]
+    fn warns_about_paths_in_macros() {
+        let source = concat!(
+            "fn check(auth: Auth) {\n",
+            "    assert_eq!(crate::auth::jwt::VALUE, auth::LEN);\n",
+            "    println!(\"{}\", ::auth::VALUE + crate::db::Pool::SIZE);\n",
+            "}\n",
+            "macro_rules! token {\n",
+            "    ($auth:ident) => { $crate::auth::Token::new($auth::VALUE) };\n",
+            "}\n",
+        );
+        assert_eq!(
+            macro_warnings(source, &["crate::auth"], &[]),
+            vec![
+                "`crate::auth::jwt::VALUE` in a macro in src/lib.rs, line 2, is not rewritten: \
+                 change it to `my_crate_auth::jwt::VALUE`",
+                "`auth::LEN` in a macro in src/lib.rs, line 2, is not rewritten: \
+                 change it to `my_crate_auth::LEN`",
+                "`crate::auth::Token::new` in a macro in src/lib.rs, line 6, is not rewritten: \
+                 change it to `my_crate_auth::Token::new`",
+            ]
+        );
+    }
+
+    #[test]
+    fn warns_about_paths_in_macros_of_extracted_modules() {
+        let source = concat!(
+            "fn check() {\n",
+            "    assert_eq!(crate::auth::jwt::VALUE, self::jwt::VALUE);\n",
+            "    assert!(crate::db::Pool::SIZE > super::db::NAME.len());\n",
+            "}\n",
+        );
+        assert_eq!(
+            macro_warnings(source, &["crate::auth"], &["auth"]),
+            vec![
+                "`crate::auth::jwt::VALUE` in a macro in src/lib.rs, line 2, is not rewritten: \
+                 change it to `crate::jwt::VALUE`",
+                "`crate::db::Pool::SIZE` in a macro in src/lib.rs, line 3, points to crate::db, \
+                 which stays in the parent crate",
+                "`super::db::NAME` in a macro in src/lib.rs, line 3, points outside of the \
+                 extracted modules",
+            ]
+        );
+    }
+
+    #[test]
+    fn rewrites_files_of_the_parent_crate() {
+        let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/layered");
+        let crate_modules = list_modules(&crate_root).unwrap();
+        let extracted = [ModuleName::from("crate::db")];
+        let mapping = ModuleMapping::new(&extracted, &extracted, &crate_modules.modules).unwrap();
+        let rewrites =
+            rewrite_parent_imports(&crate_root, &crate_modules, &mapping, "layered-db").unwrap();
+        let rewritten = rewrites
+            .iter()
+            .map(|rewrite| {
+                let content = read_to_string(crate_root.join(&rewrite.file)).unwrap();
+                (rewrite.file.clone(), rewrite.apply(&content).unwrap())
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            rewritten,
+            vec![
+                (
+                    PathBuf::from("src/api/mod.rs"),
+                    read_to_string(crate_root.join("src/api/mod.rs"))
+                        .unwrap()
+                        .replace("use crate::db::Connection;", "use layered_db::Connection;")
+                ),
+                (
+                    PathBuf::from("src/api/handlers.rs"),
+                    read_to_string(crate_root.join("src/api/handlers.rs"))
+                        .unwrap()
+                        .replace("use crate::db;", "use layered_db as db;")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn reroots_absolute_imports() {
+        assert_eq!(
+            reroot("use crate::auth::jwt::Claims;", &["crate::auth"], &["auth"]),
+            Ok("use crate::jwt::Claims;".to_owned())
+        );
+        assert_eq!(
+            reroot(
+                "use crate::{auth::jwt::Claims, auth::Token};",
+                &["crate::auth"],
+                &["auth", "jwt"]
+            ),
+            Ok("use crate::{jwt::Claims, Token};".to_owned())
+        );
+        assert_eq!(
+            reroot(
+                "use crate::auth::{self, Token};",
+                &["crate::auth"],
+                &["auth", "jwt"]
+            ),
+            Ok("use crate::{self as auth, Token};".to_owned())
+        );
+        assert_eq!(
+            reroot("use crate::auth;", &["crate::auth"], &["auth", "jwt"]),
+            Ok("use crate as auth;".to_owned())
+        );
+        assert_eq!(
+            reroot("use std::fmt;", &["crate::auth"], &["auth"]),
+            Ok("use std::fmt;".to_owned())
+        );
+    }
+
+    #[test]
+    fn reroots_relative_imports() {
+        assert_eq!(
+            reroot("use super::Token;", &["crate::auth"], &["auth", "jwt"]),
+            Ok("use super::Token;".to_owned())
+        );
+        assert_eq!(
+            reroot("use self::jwt::Claims;", &["crate::auth"], &["auth"]),
+            Ok("use self::jwt::Claims;".to_owned())
+        );
+        assert_eq!(
+            reroot(
+                "use super::super::auth::Token;",
+                &["crate::auth", "crate::services::session"],
+                &["services", "session"]
+            ),
+            Ok("use crate::auth::Token;".to_owned())
+        );
+        assert_eq!(
+            reroot(
+                "use super::services::session::Session;",
+                &["crate::auth", "crate::services::session"],
+                &["auth"]
+            ),
+            Ok("use crate::session::Session;".to_owned())
+        );
+    }
+
+    #[test]
+    fn fails_to_reroot_super_out_of_extracted_modules() {
+        assert_eq!(
+            reroot("use super::Config;", &["crate::auth"], &["auth"]),
+            Err(RerootError::SuperOutsideOfExtractedModules)
+        );
+        assert_eq!(
+            reroot(
+                "use super::super::db::Pool;",
+                &["crate::auth"],
+                &["auth", "jwt"]
+            ),
+            Err(RerootError::SuperOutsideOfExtractedModules)
+        );
+    }
+
+    #[test]
+    fn reroots_files_of_the_extracted_module() {
+        let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/nested");
+        let crate_modules = list_modules(&crate_root).unwrap();
+        let extracted = [ModuleName::from("crate::auth")];
+        let mapping = ModuleMapping::new(&extracted, &extracted, &crate_modules.modules).unwrap();
+        let rewrites = reroot_extracted_paths(&crate_root, &crate_modules, &mapping).unwrap();
+        let rewritten = rewrites
+            .iter()
+            .map(|rewrite| {
+                let content = read_to_string(crate_root.join(&rewrite.file)).unwrap();
+                (rewrite.file.clone(), rewrite.apply(&content).unwrap())
+            })
+            .collect::<Vec<_>>();
+        let original = |file: &str| read_to_string(crate_root.join(file)).unwrap();
+        assert_eq!(
+            rewritten,
+            vec![
+                (
+                    PathBuf::from("src/auth/mod.rs"),
+                    original("src/auth/mod.rs").replace("crate::auth::jwt", "crate::jwt")
+                ),
+                (
+                    PathBuf::from("src/auth/jwt.rs"),
+                    original("src/auth/jwt.rs")
+                        .replace("use crate::auth::session;", "use crate::session;")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_super_out_of_extracted_modules() {
+        let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/layered");
+        let crate_modules = list_modules(&crate_root).unwrap();
+        let extracted = [ModuleName::from("crate::api::handlers")];
+        let mapping = ModuleMapping::new(&extracted, &extracted, &crate_modules.modules).unwrap();
+        assert_eq!(
+            reroot_extracted_paths(&crate_root, &crate_modules, &mapping),
+            Err(RewriteError::SuperOutsideOfExtractedModules {
+                file: PathBuf::from("src/api/handlers.rs"),
+                module: ModuleName::from("crate::api::handlers"),
+            })
+        );
+    }
+
+    #[test]
+    fn removes_module_declarations() {
+        let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/layered");
+        let crate_modules = list_modules(&crate_root).unwrap();
+        let extracted = [ModuleName::from("crate::api::handlers")];
+        let mapping = ModuleMapping::new(&extracted, &extracted, &crate_modules.modules).unwrap();
+        let changes = remove_module_declarations(&crate_root, &crate_modules, &mapping).unwrap();
+        assert_eq!(changes.len(), 1);
+        let content = read_to_string(crate_root.join("src/api/mod.rs")).unwrap();
+        assert_eq!(
+            changes[0].apply(&content).unwrap(),
+            content.replace("mod handlers;\n\n", "")
+        );
+    }
+
+    #[test]
+    fn moves_cfg_of_gated_modules() {
+        let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/deps");
+        let crate_modules = list_modules(&crate_root).unwrap();
+        let extracted = [ModuleName::from("crate::metrics")];
+        let mapping = ModuleMapping::new(&extracted, &extracted, &crate_modules.modules).unwrap();
+        let changes = remove_module_declarations(&crate_root, &crate_modules, &mapping).unwrap();
+        let content = read_to_string(crate_root.join("src/metrics.rs")).unwrap();
+        assert_eq!(
+            changes[1].apply(&content).unwrap(),
+            format!("#![cfg(feature = \"metrics\")]\n{content}")
+        );
+        assert_eq!(
+            dropped_declaration_attributes(&crate_root, &crate_modules, &mapping).unwrap(),
+            vec!["`#[macro_use]` on the declaration of crate::metrics in src/lib.rs is dropped"]
+        );
+    }
+
+    #[test]
+    fn moves_declaration_docs_into_module() {
+        let content = "mod api;\n\n/// Helpers.\n#[allow(dead_code)]\n#[cfg(test)]\nmod util;\n";
+        let file = syn::parse_file(content).unwrap();
+        let syn::Item::Mod(item) = &file.items[1] else {
+            panic!("expected a module");
+        };
+        assert_eq!(
+            inner_attributes(content, &item.attrs),
+            "//! Helpers.\n#![allow(dead_code)]\n#![cfg(test)]\n"
+        );
+        assert_eq!(
+            &content[whole_lines(content, syn::spanned::Spanned::span(item).byte_range())],
+            "/// Helpers.\n#[allow(dead_code)]\n#[cfg(test)]\nmod util;\n"
+        );
+    }
+
+    #[test]
+    fn lifts_inline_modules() {
+        let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/inline");
+        let crate_modules = list_modules(&crate_root).unwrap();
+        let extracted = [ModuleName::from("crate::module_a")];
+        let mapping = ModuleMapping::new(&extracted, &extracted, &crate_modules.modules).unwrap();
+        assert_eq!(
+            lift_inline_modules(&crate_root, &crate_modules, &mapping).unwrap(),
+            vec![LiftedModule {
+                new_module: ModuleName::from("crate"),
+                content: "use crate::module_b;\nmod module_b {\n    use foo::Bar;\n}\n".to_owned(),
+            }]
+        );
+        assert_eq!(dedent("    a\n\n      b"), "a\n\n  b\n");
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use crate::dependencies::{
+    GetAllModuleFilesError, ModuleName, UseStatementMap, check_self_contained,
+};
+use crate::graph::ModuleGraph;
+
+/// A dependency between two modules of the crate.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+pub struct Dependency {
+    pub from: ModuleName,
+    pub to: ModuleName,
+}
+
+/// A subtree of the module tree that could become a separate crate.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Suggestion {
+    /// Root of the subtree.
+    pub module: ModuleName,
+    /// Number of modules in the subtree, including its root.
+    pub modules: usize,
+    /// Number of lines in the subtree.
+    pub lines: usize,
+    /// Modules outside of the subtree that it imports, as reported by [check_self_contained].
+    pub imports_outside: Vec<ModuleName>,
+    /// Dependencies from the subtree to the rest of the crate.
+    pub blocking_edges: Vec<Dependency>,
+    /// Whether a dependency cycle goes through both the subtree and the rest of the crate.
+    pub crosses_cycle: bool,
+}
+
+impl Suggestion {
+    /// Whether the subtree can be extracted as is.
+    pub fn is_extractable(&self) -> bool {
+        self.imports_outside.is_empty() && self.blocking_edges.is_empty() && !self.crosses_cycle
+    }
+}
+
+/// Ranks every subtree of the module tree (except the crate root) by how cheaply it could be
+/// extracted: subtrees that are not part of a cycle with the rest of the crate first, then the
+/// ones with the fewest blocking dependencies, then the biggest ones.
+pub fn suggest_extractions(
+    graph: &ModuleGraph,
+    use_statements: &UseStatementMap,
+) -> Vec<Suggestion> {
+    let mut suggestions = graph
+        .modules()
+        .keys()
+        .filter(|module| module.parent().is_some())
+        .map(|module| suggest(graph, use_statements, module))
+        .collect::<Vec<_>>();
+    suggestions.sort_by(|a, b| {
+        (a.crosses_cycle, a.blocking_edges.len(), b.lines, &a.module).cmp(&(
+            b.crosses_cycle,
+            b.blocking_edges.len(),
+            a.lines,
+            &b.module,
+        ))
+    });
+    suggestions
+}
+
+fn suggest(graph: &ModuleGraph, use_statements: &UseStatementMap, root: &ModuleName) -> Suggestion {
+    let subtree = graph.subtree(root).cloned().collect::<BTreeSet<_>>();
+    let imports_outside = match check_self_contained(std::slice::from_ref(root), use_statements) {
+        Err(GetAllModuleFilesError::ModuleIsNotSelfContained(modules)) => modules.0,
+        _ => Vec::new(),
+    };
+    let blocking_edges = subtree
+        .iter()
+        .flat_map(|from| {
+            graph
+                .dependencies(from)
+                .filter(|to| !subtree.contains(*to))
+                .map(|to| Dependency {
+                    from: from.clone(),
+                    to: to.clone(),
+                })
+        })
+        .collect::<Vec<_>>();
+    let depended_on = reachable(&subtree, |module| graph.dependencies(module));
+    let depending = reachable(&subtree, |module| graph.dependents(module));
+    Suggestion {
+        module: root.clone(),
+        modules: subtree.len(),
+        lines: subtree
+            .iter()
+            .filter_map(|module| graph.module(module))
+            .map(|info| info.lines)
+            .sum(),
+        imports_outside,
+        blocking_edges,
+        crosses_cycle: depended_on.intersection(&depending).next().is_some(),
+    }
+}
+
+/// Modules outside of `subtree` reachable from it by following `next`.
+fn reachable<'a, I>(
+    subtree: &BTreeSet<ModuleName>,
+    next: impl Fn(&ModuleName) -> I,
+) -> BTreeSet<ModuleName>
+where
+    I: Iterator<Item = &'a ModuleName>,
+{
+    let mut visited = BTreeSet::new();
+    let mut queue = subtree.iter().cloned().collect::<VecDeque<_>>();
+    while let Some(module) = queue.pop_front() {
+        for neighbour in next(&module) {
+            if !subtree.contains(neighbour) && visited.insert(neighbour.clone()) {
+                queue.push_back(neighbour.clone());
+            }
+        }
+    }
+    visited
+}
+
+/// Formats the suggestions as a human-readable list, with what blocks each of them.
+pub fn to_text(suggestions: &[Suggestion]) -> String {
+    let mut text = String::new();
+    for (rank, suggestion) in suggestions.iter().enumerate() {
+        write!(
+            text,
+            "{}. {} ({} modules, {} lines)",
+            rank + 1,
+            suggestion.module,
+            suggestion.modules,
+            suggestion.lines
+        )
+        .unwrap();
+        if suggestion.is_extractable() {
+            text.push_str(": extractable\n");
+            continue;
+        }
+        text.push('\n');
+        if suggestion.crosses_cycle {
+            text.push_str("   part of a dependency cycle with the rest of the crate\n");
+        }
+        for edge in &suggestion.blocking_edges {
+            writeln!(text, "   {} -> {}", edge.from, edge.to).unwrap();
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use pretty_assertions::assert_eq;
+
+    use crate::dependencies::list_modules;
+    use crate::graph::ModuleGraph;
+
+    use super::{Dependency, Suggestion, suggest_extractions};
+
+    fn layered_suggestions() -> Vec<Suggestion> {
+        let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/layered");
+        let crate_modules = list_modules(&crate_root).unwrap();
+        suggest_extractions(
+            &ModuleGraph::new(&crate_modules),
+            &crate_modules.use_statements,
+        )
+    }
+
+    #[test]
+    fn ranks_leaves_first() {
+        let suggestions = layered_suggestions();
+        assert_eq!(
+            suggestions
+                .iter()
+                .map(|s| (s.module.to_string(), s.blocking_edges.len()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("crate::util".to_owned(), 0),
+                ("crate::db".to_owned(), 1),
+                ("crate::api::handlers".to_owned(), 2),
+                ("crate::api".to_owned(), 3),
+            ]
+        );
+        assert!(suggestions[0].is_extractable());
+    }
+
+    #[test]
+    fn lists_blockers() {
+        let suggestions = layered_suggestions();
+        let db = suggestions
+            .iter()
+            .find(|s| s.module == "crate::db".into())
+            .unwrap();
+        assert_eq!(
+            db,
+            &Suggestion {
+                module: "crate::db".into(),
+                modules: 1,
+                lines: 11,
+                imports_outside: vec!["crate::util".into()],
+                blocking_edges: vec![Dependency {
+                    from: "crate::db".into(),
+                    to: "crate::util".into(),
+                }],
+                crosses_cycle: false,
+            }
+        );
+    }
+
+    #[test]
+    fn detects_cycles_crossing_the_boundary() {
+        let crate_root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/cyclic");
+        let crate_modules = list_modules(&crate_root).unwrap();
+        let suggestions = suggest_extractions(
+            &ModuleGraph::new(&crate_modules),
+            &crate_modules.use_statements,
+        );
+        assert_eq!(
+            suggestions
+                .iter()
+                .map(|s| (s.module.to_string(), s.crosses_cycle))
+                .collect::<Vec<_>>(),
+            vec![
+                ("crate::c".to_owned(), false),
+                ("crate::a".to_owned(), true),
+                ("crate::b".to_owned(), true),
+            ]
+        );
+    }
+}
@@ -0,0 +1,480 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf, absolute};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum JournalError {
+    #[error("failed to read the journal: {0}")]
+    Read(io::Error),
+    #[error("failed to parse the journal: {0}")]
+    Parse(serde_json::Error),
+    #[error("failed to write the journal: {0}")]
+    Write(io::Error),
+    #[error("{0} changed since it was written, not undoing")]
+    FileChanged(PathBuf),
+    #[error("failed to undo some changes, fix them and undo again: {}", format_failures(.failures))]
+    Incomplete {
+        failures: Vec<(PathBuf, io::Error)>,
+        /// The steps that failed, to run again.
+        remaining: Journal,
+    },
+}
+
+fn format_failures(failures: &[(PathBuf, io::Error)]) -> String {
+    failures
+        .iter()
+        .map(|(path, error)| format!("{}: {error}", path.display()))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Reverts a single file system operation. Paths are absolute.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+enum Undo {
+    RemoveFile(PathBuf),
+    RemoveDir(PathBuf),
+    CreateDir(PathBuf),
+    Restore { path: PathBuf, content: Vec<u8> },
+    MoveBack { from: PathBuf, to: PathBuf },
+}
+
+impl Undo {
+    fn path(&self) -> &Path {
+        match self {
+            Undo::RemoveFile(path)
+            | Undo::RemoveDir(path)
+            | Undo::CreateDir(path)
+            | Undo::Restore { path, .. } => path,
+            Undo::MoveBack { from, .. } => from,
+        }
+    }
+
+    /// Whether the step reverts what the transaction did to the file at `path`.
+    fn reverts_file(&self, path: &Path) -> bool {
+        match self {
+            Undo::RemoveFile(file) | Undo::Restore { path: file, .. } => file == path,
+            Undo::MoveBack { from, .. } => path.starts_with(from),
+            Undo::RemoveDir(_) | Undo::CreateDir(_) => false,
+        }
+    }
+
+    fn run(&self) -> io::Result<()> {
+        match self {
+            Undo::RemoveFile(path) => fs::remove_file(path),
+            Undo::RemoveDir(path) => fs::remove_dir(path),
+            Undo::CreateDir(path) => fs::create_dir(path),
+            Undo::Restore { path, content } => fs::write(path, content),
+            Undo::MoveBack { from, to } => fs::rename(from, to),
+        }
+    }
+}
+
+/// Record of a successful transaction, to undo it later.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Journal {
+    /// How to revert the operations, in the order they were done.
+    undo: Vec<Undo>,
+    /// SHA-256 of the files written or moved by the transaction, right after it.
+    hashes: BTreeMap<PathBuf, String>,
+}
+
+impl Journal {
+    pub fn load(path: &Path) -> Result<Self, JournalError> {
+        let content = fs::read_to_string(path).map_err(JournalError::Read)?;
+        serde_json::from_str(&content).map_err(JournalError::Parse)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), JournalError> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(JournalError::Write)?;
+        }
+        let content = serde_json::to_string_pretty(self).map_err(JournalError::Parse)?;
+        fs::write(path, content + "\n").map_err(JournalError::Write)
+    }
+
+    /// Reverts the transaction, unless one of the files it wrote changed since.
+    ///
+    /// A step that fails, such as removing a directory where new files were created, doesn't
+    /// stop the others. The steps that failed are returned as a journal to run again.
+    pub fn undo(&self) -> Result<(), JournalError> {
+        for (path, hash) in &self.hashes {
+            if hash_file(path).ok().as_ref() != Some(hash) {
+                return Err(JournalError::FileChanged(path.clone()));
+            }
+        }
+        let mut failures = Vec::new();
+        let mut remaining = Vec::new();
+        for undo in self.undo.iter().rev() {
+            if let Err(error) = undo.run() {
+                failures.push((undo.path().to_owned(), error));
+                remaining.push(undo.clone());
+            }
+        }
+        if failures.is_empty() {
+            return Ok(());
+        }
+        remaining.reverse();
+        // Only the files that weren't restored are still as the transaction left them.
+        let hashes = self
+            .hashes
+            .iter()
+            .filter(|(path, _)| remaining.iter().any(|undo| undo.reverts_file(path)))
+            .map(|(path, hash)| (path.clone(), hash.clone()))
+            .collect();
+        Err(JournalError::Incomplete {
+            failures,
+            remaining: Journal {
+                undo: remaining,
+                hashes,
+            },
+        })
+    }
+}
+
+/// File system operations that are undone unless they all succeed.
+///
+/// Every operation records how to revert it, so that a failure halfway through an extraction
+/// restores the original state instead of leaving a half-refactored tree behind.
+#[derive(Debug, Default)]
+pub struct Transaction {
+    undo: Vec<Undo>,
+}
+
+impl Transaction {
+    /// Runs `operations`, rolling back what they did if they fail. On success, returns the
+    /// journal to undo them later.
+    pub fn run<E>(
+        operations: impl FnOnce(&mut Transaction) -> Result<(), E>,
+    ) -> Result<Journal, E> {
+        let mut transaction = Transaction::default();
+        if let Err(error) = operations(&mut transaction) {
+            transaction.rollback();
+            return Err(error);
+        }
+        Ok(transaction.into_journal())
+    }
+
+    /// Runs `operations` like [Transaction::run], and saves the journal to `journal_path` as
+    /// part of the transaction: if it can't be saved, the operations are rolled back too.
+    pub fn run_and_save<E: From<JournalError>>(
+        journal_path: &Path,
+        operations: impl FnOnce(&mut Transaction) -> Result<(), E>,
+    ) -> Result<(), E> {
+        let mut transaction = Transaction::default();
+        if let Err(error) = operations(&mut transaction) {
+            transaction.rollback();
+            return Err(error);
+        }
+        if let Err(error) = transaction.journal().save(journal_path) {
+            transaction.rollback();
+            return Err(error.into());
+        }
+        Ok(())
+    }
+
+    fn into_journal(self) -> Journal {
+        self.journal()
+    }
+
+    fn journal(&self) -> Journal {
+        let mut files = Vec::new();
+        for undo in &self.undo {
+            match undo {
+                Undo::RemoveFile(path) | Undo::Restore { path, .. } => files.push(path.clone()),
+                Undo::MoveBack { from, .. } => {
+                    list_files(from, &mut files).unwrap_or_else(|error| {
+                        tracing::warn!("failed to list {}: {error}", from.display())
+                    })
+                }
+                Undo::RemoveDir(_) | Undo::CreateDir(_) => {}
+            }
+        }
+        let hashes = files
+            .into_iter()
+            .filter_map(|file| Some((file.clone(), hash_file(&file).ok()?)))
+            .collect();
+        Journal {
+            undo: self.undo.clone(),
+            hashes,
+        }
+    }
+
+    /// Creates `path` and its missing parents.
+    pub fn create_dir_all(&mut self, path: &Path) -> io::Result<()> {
+        let missing = path
+            .ancestors()
+            .take_while(|ancestor| !ancestor.as_os_str().is_empty() && !ancestor.exists())
+            .map(Path::to_owned)
+            .collect::<Vec<_>>();
+        for directory in missing.into_iter().rev() {
+            fs::create_dir(&directory)?;
+            self.undo.push(Undo::RemoveDir(absolute(directory)?));
+        }
+        Ok(())
+    }
+
+    /// Writes `content` to `path`, creating its parent directories if needed.
+    pub fn write(&mut self, path: &Path, content: impl AsRef<[u8]>) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            self.create_dir_all(parent)?;
+        }
+        let undo = match fs::read(path) {
+            Ok(content) => Undo::Restore {
+                path: absolute(path)?,
+                content,
+            },
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                Undo::RemoveFile(absolute(path)?)
+            }
+            Err(error) => return Err(error),
+        };
+        fs::write(path, content)?;
+        self.undo.push(undo);
+        Ok(())
+    }
+
+    /// Removes the empty directory `path`.
+    pub fn remove_dir(&mut self, path: &Path) -> io::Result<()> {
+        fs::remove_dir(path)?;
+        self.undo.push(Undo::CreateDir(absolute(path)?));
+        Ok(())
+    }
+
+    /// Moves the file or directory `from` to `to`, creating the parent directories of `to` if
+    /// needed.
+    pub fn rename(&mut self, from: &Path, to: &Path) -> io::Result<()> {
+        if to.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{} already exists", to.display()),
+            ));
+        }
+        if let Some(parent) = to.parent() {
+            self.create_dir_all(parent)?;
+        }
+        fs::rename(from, to)?;
+        self.undo.push(Undo::MoveBack {
+            from: absolute(to)?,
+            to: absolute(from)?,
+        });
+        Ok(())
+    }
+
+    /// Reverts the operations, most recent first. Failures are logged rather than returned, to
+    /// revert as much as possible.
+    fn rollback(self) {
+        for undo in self.undo.into_iter().rev() {
+            if let Err(error) = undo.run() {
+                tracing::error!("failed to roll back {undo:?}: {error}");
+            }
+        }
+    }
+}
+
+/// Adds `path` to `files` if it is a file, or all the files under it if it is a directory.
+pub(crate) fn list_files(path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !path.is_dir() {
+        files.push(path.to_owned());
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(path)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for entry in entries {
+        list_files(&entry, files)?;
+    }
+    Ok(())
+}
+
+/// SHA-256 of the content of a file, in hexadecimal.
+pub(crate) fn hash_file(path: &Path) -> io::Result<String> {
+    let digest = Sha256::digest(fs::read(path)?);
+    Ok(digest.iter().map(|byte| format!("{byte:02x}")).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{self, create_dir_all, read_to_string, remove_dir_all};
+    use std::io;
+
+    use pretty_assertions::assert_eq;
+
+    use super::{Journal, JournalError, Transaction};
+
+    #[test]
+    fn rolls_back_on_failure() {
+        let root = temp_dir().join("extricrate_transaction_rollback");
+        remove_dir_all(&root).unwrap_or_default();
+        create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/lib.rs"), "mod auth;\n").unwrap();
+        fs::write(root.join("src/auth.rs"), "pub struct Token;\n").unwrap();
+
+        let result = Transaction::run(|transaction| {
+            transaction.write(&root.join("src/lib.rs"), "")?;
+            transaction.write(&root.join("new/src/lib.rs"), "pub struct Token;\n")?;
+            transaction.rename(&root.join("src/auth.rs"), &root.join("new/src/auth.rs"))?;
+            transaction.rename(
+                &root.join("src/missing.rs"),
+                &root.join("new/src/missing.rs"),
+            )
+        });
+
+        assert_eq!(
+            result.map_err(|error| error.kind()),
+            Err(io::ErrorKind::NotFound)
+        );
+        assert_eq!(
+            read_to_string(root.join("src/lib.rs")).unwrap(),
+            "mod auth;\n"
+        );
+        assert_eq!(
+            read_to_string(root.join("src/auth.rs")).unwrap(),
+            "pub struct Token;\n"
+        );
+        assert!(!root.join("new").exists());
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn rolls_back_when_the_journal_cannot_be_saved() {
+        let root = temp_dir().join("extricrate_transaction_unsaved");
+        remove_dir_all(&root).unwrap_or_default();
+        create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/lib.rs"), "mod auth;\n").unwrap();
+        // A file where the directory of the journal should be.
+        fs::write(root.join("target"), "").unwrap();
+
+        let result = Transaction::run_and_save(&root.join("target/journal.json"), |transaction| {
+            transaction
+                .write(&root.join("src/lib.rs"), "")
+                .map_err(JournalError::Write)?;
+            transaction
+                .write(&root.join("new/src/lib.rs"), "pub struct Token;\n")
+                .map_err(JournalError::Write)
+        });
+
+        assert!(matches!(result, Err(JournalError::Write(_))));
+        assert_eq!(
+            read_to_string(root.join("src/lib.rs")).unwrap(),
+            "mod auth;\n"
+        );
+        assert!(!root.join("new").exists());
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn restores_binary_files() {
+        let root = temp_dir().join("extricrate_transaction_binary");
+        remove_dir_all(&root).unwrap_or_default();
+        create_dir_all(&root).unwrap();
+        let content = [0xff, 0xfe, b'\n'];
+        fs::write(root.join("data.bin"), content).unwrap();
+
+        let journal =
+            Transaction::run(|transaction| transaction.write(&root.join("data.bin"), "")).unwrap();
+        let journal_path = root.join("journal.json");
+        journal.save(&journal_path).unwrap();
+        Journal::load(&journal_path).unwrap().undo().unwrap();
+
+        assert_eq!(fs::read(root.join("data.bin")).unwrap(), content);
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn keeps_failed_steps_to_undo_again() {
+        let root = temp_dir().join("extricrate_transaction_incomplete");
+        remove_dir_all(&root).unwrap_or_default();
+        create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/lib.rs"), "mod auth;\n").unwrap();
+        fs::write(root.join("src/auth.rs"), "pub struct Token;\n").unwrap();
+
+        let journal = Transaction::run(|transaction| {
+            transaction.write(&root.join("src/lib.rs"), "")?;
+            transaction.rename(&root.join("src/auth.rs"), &root.join("new/src/lib.rs"))
+        })
+        .unwrap();
+        // Files the transaction doesn't know about, e.g. from building the new crate.
+        fs::write(root.join("new/Cargo.lock"), "").unwrap();
+
+        let Err(JournalError::Incomplete {
+            failures,
+            remaining,
+        }) = journal.undo()
+        else {
+            panic!("expected the undo to be incomplete");
+        };
+        assert_eq!(
+            failures
+                .iter()
+                .map(|(path, _)| path.ends_with("new"))
+                .collect::<Vec<_>>(),
+            vec![true]
+        );
+        assert_eq!(
+            read_to_string(root.join("src/lib.rs")).unwrap(),
+            "mod auth;\n"
+        );
+        assert_eq!(
+            read_to_string(root.join("src/auth.rs")).unwrap(),
+            "pub struct Token;\n"
+        );
+
+        fs::remove_file(root.join("new/Cargo.lock")).unwrap();
+        remaining.undo().unwrap();
+        assert!(!root.join("new").exists());
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn keeps_changes_on_success() {
+        let root = temp_dir().join("extricrate_transaction_commit");
+        remove_dir_all(&root).unwrap_or_default();
+        Transaction::run(|transaction| transaction.write(&root.join("src/lib.rs"), "")).unwrap();
+        assert_eq!(read_to_string(root.join("src/lib.rs")).unwrap(), "");
+        remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn undoes_from_journal() {
+        let root = temp_dir().join("extricrate_transaction_undo");
+        remove_dir_all(&root).unwrap_or_default();
+        create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/lib.rs"), "mod auth;\n").unwrap();
+        fs::write(root.join("src/auth.rs"), "pub struct Token;\n").unwrap();
+
+        let journal = Transaction::run(|transaction| {
+            transaction.write(&root.join("src/lib.rs"), "")?;
+            transaction.rename(&root.join("src/auth.rs"), &root.join("new/src/lib.rs"))
+        })
+        .unwrap();
+        let journal_path = root.join("target/journal.json");
+        journal.save(&journal_path).unwrap();
+        let journal = Journal::load(&journal_path).unwrap();
+
+        fs::write(root.join("new/src/lib.rs"), "pub struct Changed;\n").unwrap();
+        assert!(matches!(
+            journal.undo(),
+            Err(JournalError::FileChanged(path)) if path.ends_with("new/src/lib.rs")
+        ));
+
+        fs::write(root.join("new/src/lib.rs"), "pub struct Token;\n").unwrap();
+        journal.undo().unwrap();
+        assert_eq!(
+            read_to_string(root.join("src/lib.rs")).unwrap(),
+            "mod auth;\n"
+        );
+        assert_eq!(
+            read_to_string(root.join("src/auth.rs")).unwrap(),
+            "pub struct Token;\n"
+        );
+        assert!(!root.join("new").exists());
+        remove_dir_all(&root).unwrap();
+    }
+}
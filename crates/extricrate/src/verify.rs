@@ -0,0 +1,378 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use serde::Deserialize;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum VerifyError {
+    #[error("failed to run `cargo {0}`: {1}")]
+    CargoNotRunnable(String, io::Error),
+    #[error("`cargo {0}` failed: {1}")]
+    CargoFailed(String, String),
+    #[error("failed to parse the output of `cargo {0}`: {1}")]
+    OutputNotParsable(String, serde_json::Error),
+}
+
+/// Lines written by an extraction, in a file as it is once the extraction is applied.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedEdit {
+    pub file: PathBuf,
+    /// 1-based, like the lines of compiler messages.
+    pub lines: RangeInclusive<usize>,
+}
+
+impl AppliedEdit {
+    /// Lines of `content` covered by the byte range `start..end`.
+    pub fn new(file: PathBuf, content: &str, start: usize, end: usize) -> Self {
+        let line = |offset: usize| content[..offset].matches('\n').count() + 1;
+        let last = if end > start && content[..end].ends_with('\n') {
+            line(end) - 1
+        } else {
+            line(end)
+        };
+        Self {
+            file,
+            lines: line(start)..=last,
+        }
+    }
+}
+
+/// Error reported by the compiler after an extraction.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompilerError {
+    /// Absolute path of the file of the primary span, if any.
+    pub file: Option<PathBuf>,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+    /// The message as the compiler prints it, with the source snippet.
+    pub rendered: String,
+    /// The edit of the extraction on the line of the error, which probably caused it.
+    pub edit: Option<AppliedEdit>,
+}
+
+impl fmt::Display for CompilerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.rendered)?;
+        if let Some(edit) = &self.edit {
+            write!(
+                f,
+                "note: probably caused by the extraction's edit of {}, ",
+                edit.file.display()
+            )?;
+            if edit.lines.start() == edit.lines.end() {
+                writeln!(f, "line {}", edit.lines.start())?;
+            } else {
+                writeln!(f, "lines {}-{}", edit.lines.start(), edit.lines.end())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+struct CargoMessage {
+    reason: String,
+    message: Option<RustcMessage>,
+}
+
+#[derive(Deserialize)]
+struct RustcMessage {
+    message: String,
+    level: String,
+    spans: Vec<RustcSpan>,
+    rendered: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RustcSpan {
+    file_name: PathBuf,
+    line_start: usize,
+    column_start: usize,
+    is_primary: bool,
+}
+
+/// Parses the errors out of the JSON messages of cargo. Their paths are relative to
+/// `workspace_root`.
+fn parse_errors(
+    output: &str,
+    workspace_root: &Path,
+) -> Result<Vec<CompilerError>, serde_json::Error> {
+    let mut errors = Vec::new();
+    for line in output.lines().filter(|line| line.starts_with('{')) {
+        let message: CargoMessage = serde_json::from_str(line)?;
+        let Some(message) = message
+            .message
+            .filter(|message| message.level == "error")
+            .filter(|_| message.reason == "compiler-message")
+        else {
+            continue;
+        };
+        let span = message.spans.iter().find(|span| span.is_primary);
+        errors.push(CompilerError {
+            file: span.map(|span| workspace_root.join(&span.file_name)),
+            line: span.map_or(0, |span| span.line_start),
+            column: span.map_or(0, |span| span.column_start),
+            rendered: message
+                .rendered
+                .unwrap_or_else(|| format!("error: {}\n", message.message)),
+            message: message.message,
+            edit: None,
+        });
+    }
+    Ok(errors)
+}
+
+/// Output of a cargo command.
+struct CargoOutput {
+    command: String,
+    success: bool,
+    stdout: String,
+    stderr: String,
+}
+
+impl CargoOutput {
+    /// The output of the command, if it succeeded.
+    fn into_stdout(self) -> Result<String, VerifyError> {
+        if !self.success {
+            return Err(VerifyError::CargoFailed(self.command, self.stderr));
+        }
+        Ok(self.stdout)
+    }
+
+    /// The compiler errors in the JSON messages of the command, whose paths are relative to
+    /// `workspace_root`. Compilation errors make cargo fail too, but if it failed without
+    /// any, something else went wrong.
+    fn compiler_errors(self, workspace_root: &Path) -> Result<Vec<CompilerError>, VerifyError> {
+        let errors = parse_errors(&self.stdout, workspace_root)
+            .map_err(|error| VerifyError::OutputNotParsable(self.command.clone(), error))?;
+        if errors.is_empty() {
+            self.into_stdout()?;
+        }
+        Ok(errors)
+    }
+}
+
+/// Runs cargo in `directory`, whether it succeeds or not.
+fn run_cargo(directory: &Path, args: &[&str]) -> Result<CargoOutput, VerifyError> {
+    let command = args.join(" ");
+    let output = Command::new(std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_owned()))
+        .args(args)
+        .current_dir(directory)
+        .output()
+        .map_err(|error| VerifyError::CargoNotRunnable(command.clone(), error))?;
+    Ok(CargoOutput {
+        command,
+        success: output.status.success(),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    })
+}
+
+/// Path of the workspace containing the crate in `directory`.
+fn workspace_root(directory: &Path) -> Result<PathBuf, VerifyError> {
+    let manifest = run_cargo(
+        directory,
+        &["locate-project", "--workspace", "--message-format", "plain"],
+    )?
+    .into_stdout()?;
+    Ok(Path::new(manifest.trim())
+        .parent()
+        .unwrap_or(directory)
+        .to_owned())
+}
+
+#[derive(Deserialize)]
+struct CargoMetadata {
+    target_directory: PathBuf,
+}
+
+/// The directory where cargo builds the crate in `directory`, shared by its workspace.
+pub fn target_directory(directory: &Path) -> Result<PathBuf, VerifyError> {
+    let args = ["metadata", "--format-version", "1", "--no-deps"];
+    let metadata = run_cargo(directory, &args)?.into_stdout()?;
+    serde_json::from_str::<CargoMetadata>(&metadata)
+        .map(|metadata| metadata.target_directory)
+        .map_err(|error| VerifyError::OutputNotParsable(args.join(" "), error))
+}
+
+/// Resolves `.` and `..` components and symbolic links when possible, to compare paths.
+fn normalize(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_owned())
+}
+
+/// Attributes the errors to the edits on the same line of the same file.
+fn attribute_errors(errors: &mut [CompilerError], edits: &[AppliedEdit]) {
+    let edits = edits
+        .iter()
+        .map(|edit| (normalize(&edit.file), edit))
+        .collect::<Vec<_>>();
+    for error in errors {
+        let Some(file) = error.file.as_deref().map(normalize) else {
+            continue;
+        };
+        error.edit = edits
+            .iter()
+            .find(|(edit_file, edit)| edit_file == &file && edit.lines.contains(&error.line))
+            .map(|(_, edit)| (*edit).clone());
+    }
+}
+
+/// Checks that the crates at `crate_roots` compile after an extraction, with `cargo check` and,
+/// with `build_tests`, `cargo test --no-run`. Returns the compiler errors, attributed to the
+/// `edits` that probably caused them.
+pub fn verify_extraction(
+    crate_roots: &[&Path],
+    edits: &[AppliedEdit],
+    build_tests: bool,
+) -> Result<Vec<CompilerError>, VerifyError> {
+    let mut commands = vec![vec![
+        "check",
+        "--workspace",
+        "--all-targets",
+        "--message-format=json",
+    ]];
+    if build_tests {
+        commands.push(vec![
+            "test",
+            "--workspace",
+            "--no-run",
+            "--message-format=json",
+        ]);
+    }
+    let mut errors = Vec::<CompilerError>::new();
+    for crate_root in crate_roots {
+        // Cargo creates them in a new crate that isn't part of a workspace. They would prevent
+        // undoing the extraction.
+        let artifacts = ["Cargo.lock", "target"]
+            .map(|artifact| crate_root.join(artifact))
+            .into_iter()
+            .filter(|artifact| !artifact.exists())
+            .collect::<Vec<_>>();
+        let result = check_crate(crate_root, &commands, &mut errors);
+        for artifact in artifacts.iter().filter(|artifact| artifact.exists()) {
+            let removed = if artifact.is_dir() {
+                fs::remove_dir_all(artifact)
+            } else {
+                fs::remove_file(artifact)
+            };
+            if let Err(error) = removed {
+                tracing::warn!("failed to remove {}: {error}", artifact.display());
+            }
+        }
+        result?;
+    }
+    attribute_errors(&mut errors, edits);
+    Ok(errors)
+}
+
+/// Runs the cargo `commands` in `crate_root`, and adds the errors to `errors`.
+fn check_crate(
+    crate_root: &Path,
+    commands: &[Vec<&str>],
+    errors: &mut Vec<CompilerError>,
+) -> Result<(), VerifyError> {
+    let workspace_root = workspace_root(crate_root)?;
+    for args in commands {
+        for error in run_cargo(crate_root, args)?.compiler_errors(&workspace_root)? {
+            // The crates may share a workspace, and `test` builds what `check` did.
+            if !errors.contains(&error) {
+                errors.push(error);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use pretty_assertions::assert_eq;
+
+    use super::{
+        AppliedEdit, CargoOutput, CompilerError, VerifyError, attribute_errors, parse_errors,
+    };
+
+    const OUTPUT: &str = concat!(
+        r#"{"reason":"compiler-artifact","package_id":"layered 0.1.0"}"#,
+        "\n",
+        r#"{"reason":"compiler-message","message":{"message":"unused import: `Id`","level":"warning","spans":[],"rendered":"warning: unused import\n"}}"#,
+        "\n",
+        r#"{"reason":"compiler-message","message":{"message":"unresolved import `layered_util`","level":"error","spans":[{"file_name":"src/db.rs","line_start":1,"column_start":5,"is_primary":true}],"rendered":"error[E0432]: unresolved import `layered_util`\n"}}"#,
+        "\n",
+        r#"{"reason":"build-finished","success":false}"#,
+        "\n",
+    );
+
+    #[test]
+    fn parses_compiler_errors() {
+        assert_eq!(
+            parse_errors(OUTPUT, Path::new("/work")).unwrap(),
+            vec![CompilerError {
+                file: Some(PathBuf::from("/work/src/db.rs")),
+                line: 1,
+                column: 5,
+                message: "unresolved import `layered_util`".to_owned(),
+                rendered: "error[E0432]: unresolved import `layered_util`\n".to_owned(),
+                edit: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn fails_without_compiler_errors() {
+        let output = CargoOutput {
+            command: "check --workspace --all-targets --message-format=json".to_owned(),
+            success: false,
+            stdout: concat!(
+                r#"{"reason":"compiler-message","message":{"message":"unused import: `Id`","level":"warning","spans":[],"rendered":"warning: unused import\n"}}"#,
+                "\n",
+                r#"{"reason":"build-finished","success":false}"#,
+                "\n",
+            )
+            .to_owned(),
+            stderr: "error: linking with `cc` failed\n".to_owned(),
+        };
+        let result = output.compiler_errors(Path::new("/work"));
+        assert!(matches!(
+            result,
+            Err(VerifyError::CargoFailed(command, stderr))
+                if command.starts_with("check") && stderr == "error: linking with `cc` failed\n"
+        ));
+    }
+
+    #[test]
+    fn attributes_errors_to_edits() {
+        let content = "use layered_util::Id;\n\npub struct Connection;\n";
+        let edit = AppliedEdit::new(PathBuf::from("/work/src/db.rs"), content, 0, 21);
+        assert_eq!(edit.lines, 1..=1);
+        let mut errors = parse_errors(OUTPUT, Path::new("/work")).unwrap();
+        attribute_errors(&mut errors, std::slice::from_ref(&edit));
+        assert_eq!(errors[0].edit, Some(edit));
+        assert_eq!(
+            errors[0].to_string(),
+            concat!(
+                "error[E0432]: unresolved import `layered_util`\n",
+                "note: probably caused by the extraction's edit of /work/src/db.rs, line 1\n"
+            )
+        );
+
+        let mut errors = parse_errors(OUTPUT, Path::new("/other")).unwrap();
+        attribute_errors(
+            &mut errors,
+            &[AppliedEdit::new(
+                PathBuf::from("/work/src/db.rs"),
+                content,
+                0,
+                21,
+            )],
+        );
+        assert_eq!(errors[0].edit, None);
+    }
+}
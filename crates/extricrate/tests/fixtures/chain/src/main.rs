@@ -0,0 +1,7 @@
+mod x;
+mod y;
+mod z;
+
+fn main() {
+    x::run();
+}
@@ -0,0 +1,10 @@
+use crate::y::{Y, wrap};
+use crate::z::Z;
+use crate::z::one::One;
+use crate::z::two::Two;
+
+pub fn run() {
+    let (Y, Z) = wrap(Z);
+    let _ = (One, Two);
+    println!("x depends on z more than on y, and on z through y");
+}
@@ -0,0 +1,7 @@
+use crate::z::Z;
+
+pub struct Y;
+
+pub fn wrap(z: Z) -> (Y, Z) {
+    (Y, z)
+}
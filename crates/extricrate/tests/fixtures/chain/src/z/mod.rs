@@ -0,0 +1,4 @@
+pub mod one;
+pub mod two;
+
+pub struct Z;
@@ -0,0 +1,3 @@
+use crate::b::B;
+
+pub struct A(B);
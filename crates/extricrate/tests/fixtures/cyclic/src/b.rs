@@ -0,0 +1,3 @@
+use crate::a::A;
+
+pub struct B(Option<Box<A>>);
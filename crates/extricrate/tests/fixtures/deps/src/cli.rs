@@ -0,0 +1,8 @@
+use clap::Parser;
+
+#[derive(Parser)]
+pub struct Args;
+
+pub fn scratch() -> std::path::PathBuf {
+    tempfile::tempdir().unwrap().keep()
+}
@@ -0,0 +1,7 @@
+extern crate serde_json as json;
+
+pub mod cli;
+#[cfg(feature = "metrics")]
+#[macro_use]
+mod metrics;
+pub mod store;
@@ -0,0 +1,9 @@
+macro_rules! counter {
+    ($name:expr) => {
+        $crate::metrics::counter($name)
+    };
+}
+
+pub fn counter(name: &str) -> prometheus::IntCounter {
+    prometheus::IntCounter::new(name, name).unwrap()
+}
@@ -0,0 +1,49 @@
+use json::Value;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize)]
+pub struct Item {
+    pub id: u64,
+}
+
+pub fn save(item: &Item) -> String {
+    local_store::put(item.id);
+    serde_json::to_string(item).unwrap()
+}
+
+pub fn parse(text: &str) -> Value {
+    json::from_str(text).unwrap()
+}
+
+pub fn new_id() -> u64 {
+    random::u64(..)
+}
+
+#[cfg(unix)]
+pub fn owner() -> u32 {
+    unsafe { libc::getuid() }
+}
+
+#[cfg(feature = "metrics")]
+pub fn register(registry: &prometheus::Registry) {
+    let _ = registry;
+}
+
+#[cfg(feature = "tokio")]
+pub async fn wait() {
+    tokio::task::yield_now().await;
+}
+
+#[cfg(test)]
+mod fixtures;
+
+#[cfg(test)]
+mod tests {
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn saves() {
+        let _directory = super::fixtures::directory();
+        assert_eq!(super::save(&super::Item { id: 1 }), "{\"id\":1}");
+    }
+}
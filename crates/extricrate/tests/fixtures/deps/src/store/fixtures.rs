@@ -0,0 +1,3 @@
+pub fn directory() -> tempfile::TempDir {
+    tempfile::tempdir().unwrap()
+}
@@ -0,0 +1,4 @@
+use super::Server;
+use crate::db;
+
+pub fn handle(server: &Server) {}
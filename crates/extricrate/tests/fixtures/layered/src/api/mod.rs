@@ -0,0 +1,9 @@
+use crate::db::Connection;
+use crate::util::format;
+use serde::Serialize;
+
+mod handlers;
+
+pub struct Server {
+    connection: Connection,
+}
@@ -0,0 +1,11 @@
+use crate::util::Id;
+
+pub struct Connection {
+    id: Id,
+}
+
+impl Connection {
+    pub fn id(&self) -> &Id {
+        &self.id
+    }
+}
@@ -0,0 +1,7 @@
+mod api;
+mod db;
+mod util;
+
+use crate::api::Server;
+
+fn main() {}
@@ -0,0 +1,7 @@
+use std::fmt;
+
+pub struct Id(u64);
+
+pub fn format(id: &Id) -> String {
+    id.0.to_string()
+}
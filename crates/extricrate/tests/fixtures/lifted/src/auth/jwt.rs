@@ -0,0 +1,3 @@
+pub fn decode(_token: &str) -> bool {
+    true
+}
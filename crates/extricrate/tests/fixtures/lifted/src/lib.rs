@@ -0,0 +1,12 @@
+/// Authentication.
+pub mod auth {
+    pub mod jwt;
+
+    pub fn verify(token: &str) -> bool {
+        crate::auth::jwt::decode(token)
+    }
+}
+
+pub fn check(token: &str) -> bool {
+    auth::verify(token)
+}
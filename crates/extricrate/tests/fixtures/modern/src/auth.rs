@@ -0,0 +1,6 @@
+pub mod jwt;
+mod session;
+
+pub fn verify(token: &str) -> bool {
+    crate::auth::jwt::decode(token).is_some()
+}
@@ -0,0 +1,5 @@
+pub struct Claims;
+
+pub fn decode(_token: &str) -> Option<Claims> {
+    None
+}
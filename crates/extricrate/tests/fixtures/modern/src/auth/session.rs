@@ -0,0 +1,7 @@
+mod store;
+
+use crate::auth::jwt::Claims;
+
+pub struct Session {
+    claims: Claims,
+}
@@ -0,0 +1,5 @@
+use super::Session;
+
+pub fn load() -> Option<Session> {
+    None
+}
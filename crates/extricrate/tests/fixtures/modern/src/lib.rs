@@ -0,0 +1,5 @@
+pub mod auth;
+
+pub fn check(token: &str) -> bool {
+    auth::verify(token)
+}
@@ -0,0 +1,15 @@
+use super::session::Session;
+use crate::auth::session;
+
+pub struct Claims;
+
+impl Claims {
+    pub fn is_valid(&self) -> bool {
+        true
+    }
+}
+
+pub fn decode(_token: &str) -> Claims {
+    let _session: Option<session::Session> = None;
+    self::Claims
+}
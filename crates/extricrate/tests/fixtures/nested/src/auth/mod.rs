@@ -0,0 +1,8 @@
+pub mod jwt;
+mod session;
+
+use crate::auth::jwt::{self, Claims};
+
+pub fn verify(token: &str) -> crate::auth::jwt::Claims {
+    jwt::decode(token)
+}
@@ -0,0 +1,5 @@
+use super::jwt::Claims;
+
+pub struct Session {
+    claims: Claims,
+}
@@ -0,0 +1,7 @@
+mod server;
+mod settings;
+mod text;
+
+pub struct Config;
+
+fn main() {}
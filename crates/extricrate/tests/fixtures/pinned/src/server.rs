@@ -0,0 +1,3 @@
+use crate::settings::Settings;
+
+pub fn serve(settings: Settings) {}
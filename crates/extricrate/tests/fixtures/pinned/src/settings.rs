@@ -0,0 +1,3 @@
+use crate::Config;
+
+pub struct Settings(Config);
@@ -0,0 +1,3 @@
+pub fn capitalize(text: &str) -> String {
+    text.to_uppercase()
+}
@@ -0,0 +1,17 @@
+use serde::Serialize;
+use uuid::Uuid;
+
+#[derive(Serialize)]
+pub struct Token {
+    id: String,
+}
+
+pub fn issue(user: &str) -> Option<Token> {
+    log::info!("issuing a token for {user}");
+    regex::Regex::new("^[a-z]+$")
+        .unwrap()
+        .is_match(user)
+        .then(|| Token {
+            id: Uuid::new_v4().to_string(),
+        })
+}
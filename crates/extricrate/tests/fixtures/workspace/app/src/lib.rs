@@ -0,0 +1,9 @@
+pub mod auth;
+
+pub fn is_valid(input: &str) -> bool {
+    regex::Regex::new("^[a-z]+$").unwrap().is_match(input)
+}
+
+pub fn new_id() -> uuid::Uuid {
+    uuid::Uuid::new_v4()
+}